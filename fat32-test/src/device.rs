@@ -25,22 +25,38 @@ impl BlockDevice for FakeDevice {
     type Error = ();
     fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, ()> {
         let mut file = self.file.lock().unwrap();
-        file.seek(std::io::SeekFrom::Start(block as u64 * 512))
+        file.seek(std::io::SeekFrom::Start(block as u64 * buf.len() as u64))
             .unwrap();
-        file.read(buf).unwrap();
-        Ok(0)
+        file.read_exact(buf).unwrap();
+        Ok(buf.len())
     }
 
     fn write(&self, block: usize, buf: &[u8]) -> Result<usize, ()> {
         let mut file = self.file.lock().unwrap();
-        file.seek(std::io::SeekFrom::Start(block as u64 * 512))
+        file.seek(std::io::SeekFrom::Start(block as u64 * buf.len() as u64))
             .unwrap();
-        file.write(buf).unwrap();
-        Ok(0)
+        file.write_all(buf).unwrap();
+        Ok(buf.len())
     }
 
     fn flush(&self) -> Result<(), ()> {
         Ok(())
     }
+
+    fn read_blocks(&self, block: usize, block_size: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(std::io::SeekFrom::Start(block as u64 * block_size as u64))
+            .unwrap();
+        file.read_exact(buf).unwrap();
+        Ok(buf.len())
+    }
+
+    fn write_blocks(&self, block: usize, block_size: usize, buf: &[u8]) -> Result<usize, ()> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(std::io::SeekFrom::Start(block as u64 * block_size as u64))
+            .unwrap();
+        file.write_all(buf).unwrap();
+        Ok(buf.len())
+    }
 }
 
@@ -2,6 +2,7 @@
 #![allow(unused)]
 use crate::device::FakeDevice;
 use mfat32::Fat32;
+use std::sync::Arc;
 mod device;
 mod logging;
 mod other_fat32;
@@ -9,6 +10,18 @@ mod test1_create_list_cd;
 mod test2_read_write;
 mod test3_delete;
 mod test4_rename;
+mod test5_time;
+mod test6_metadata;
+mod test7_iter;
+mod test8_path;
+mod test9_case;
+mod test10_short_name;
+mod test11_long_name;
+mod test12_label;
+mod test13_move;
+mod test14_rename;
+mod test15_large_io;
+mod test16_empty_file;
 
 
 
@@ -19,7 +32,8 @@ fn intergenerational_test() {
     // create your fat32
     logging::init_logger();
     let device = FakeDevice::new("./test.img");
-    let fat32 = Fat32::new(device).unwrap();
+    let fat32 =
+        Fat32::with_time_provider(device, Arc::new(test5_time::StepClock::new())).unwrap();
     let root = fat32.root_dir();
     // get a directory
     // begin test
@@ -27,4 +41,22 @@ fn intergenerational_test() {
     test2_read_write::test2_read_write(root.clone());
     test3_delete::test_delete_file_and_dir(root.clone());
     test4_rename::test_rename(root.clone());
+    test5_time::test5_time(root.clone());
+    test6_metadata::test6_metadata(root.clone());
+    test7_iter::test7_iter(root.clone());
+    test8_path::test8_path(root.clone());
+    test9_case::test9_case(root.clone());
+    test10_short_name::test10_short_name(root.clone());
+    test11_long_name::test11_long_name(root.clone());
+    test12_label::test12_label(&fat32);
+    test13_move::test13_move(root.clone());
+    test14_rename::test14_rename(root.clone());
+    test15_large_io::test15_large_io(root.clone());
+    test16_empty_file::test16_empty_file(root.clone());
+    fat32.unmount().unwrap();
+    test11_long_name::test11_remount(FakeDevice::new("./test.img"));
+    test13_move::test13_remount(FakeDevice::new("./test.img"));
+    test15_large_io::test15_remount(FakeDevice::new("./test.img"));
+    test16_empty_file::test16_detach();
+    test16_empty_file::test16_remount(FakeDevice::new("./test.img"));
 }
@@ -0,0 +1,63 @@
+use fat32_trait::DirectoryLike;
+use mfat32::Dir;
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+pub fn test10_short_name(root: Arc<Dir>) {
+    test_numeric_tail(root.clone());
+    test_oem_name(root.clone());
+    test_single_entry(root.clone());
+}
+
+fn test_numeric_tail(root: Arc<Dir>) {
+    root.create_dir("test_short_name").unwrap();
+    let dir = root.open_dir("test_short_name").unwrap();
+    let mut aliases = BTreeSet::new();
+    for i in 0..8 {
+        let name = format!("long_file_name_{i}.txt");
+        dir.create_file(&name).unwrap();
+        let alias = dir.metadata(&name).unwrap().short_name;
+        assert!(alias.ends_with(".TXT"));
+        assert!(aliases.insert(alias));
+    }
+    assert!(aliases.contains("LONG_F~1.TXT"));
+    assert!(aliases.contains("LONG_F~4.TXT"));
+    // 相似的名称过多时使用哈希
+    assert!(!aliases.contains("LONG_F~5.TXT"));
+    // 删除后短文件名可以重新使用
+    dir.delete_file("long_file_name_0.txt").unwrap();
+    dir.create_file("long_file_name_x.txt").unwrap();
+    let alias = dir.metadata("long_file_name_x.txt").unwrap().short_name;
+    assert_eq!(alias, "LONG_F~1.TXT");
+    println!("test_numeric_tail passed");
+}
+
+fn test_oem_name(root: Arc<Dir>) {
+    let dir = root.open_dir("test_short_name").unwrap();
+    dir.create_file("a+b[1].c").unwrap();
+    assert_eq!(dir.metadata("a+b[1].c").unwrap().short_name, "A_B_1_~1.C");
+    dir.create_file("über.txt").unwrap();
+    assert_eq!(dir.metadata("über.txt").unwrap().short_name, "ÜBER.TXT");
+    assert!(dir.open("ÜBER.TXT").is_ok());
+    println!("test_oem_name passed");
+}
+
+fn test_single_entry(root: Arc<Dir>) {
+    root.create_dir("single").unwrap();
+    let dir = root.open_dir("single").unwrap();
+    dir.create_file("readme.txt").unwrap();
+    dir.create_file("ReadMe.md").unwrap();
+    let entries = dir.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    let offsets = entries
+        .iter()
+        .map(|e| (e.name.as_str(), e.next_offset))
+        .collect::<Vec<_>>();
+    // .和..之后，readme.txt只占用一个目录项，ReadMe.md还需要一个长目录项
+    assert_eq!(offsets, [(".", 32), ("..", 64), ("readme.txt", 96), ("ReadMe.md", 160)]);
+    assert_eq!(entries[2].short_name, "README.TXT");
+    // 重新读取后仍然是小写
+    let dir = root.open_dir("single").unwrap();
+    assert!(dir.list().unwrap().contains(&"readme.txt".to_string()));
+    println!("test_single_entry passed");
+}
@@ -0,0 +1,130 @@
+use crate::device::FakeDevice;
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{BlockDevice, Dir, Fat32, OperationError};
+
+use std::sync::Arc;
+
+pub fn test11_long_name(root: Arc<Dir>) {
+    test_unicode_name(root.clone());
+    test_name_limit(root.clone());
+}
+
+fn test_unicode_name(root: Arc<Dir>) {
+    root.create_dir("test_long_name").unwrap();
+    let dir = root.open_dir("test_long_name").unwrap();
+    let names = [
+        "文件名文件名文件名文件名文件名.txt",
+        "012345678901😀.txt",
+        "abcdefghijklm",
+        "Ünïcödé ñämé wïth äccents.md",
+    ];
+    for name in names {
+        dir.create_file(name).unwrap();
+        dir.open(name).unwrap().write(0, name.as_bytes()).unwrap();
+    }
+    // 重新从磁盘读取
+    let dir = root.open_dir("test_long_name").unwrap();
+    let list = dir.list().unwrap();
+    for name in names {
+        assert!(list.contains(&name.to_string()), "{name}");
+        let file = dir.open(name).unwrap();
+        assert_eq!(file.read(0, name.len() as u64).unwrap(), name.as_bytes());
+    }
+    let iter_names = dir
+        .iter()
+        .unwrap()
+        .map(|e| e.unwrap().name)
+        .collect::<Vec<_>>();
+    for name in names {
+        assert!(iter_names.contains(&name.to_string()), "{name}");
+    }
+    println!("test_unicode_name passed");
+}
+
+fn test_name_limit(root: Arc<Dir>) {
+    let dir = root.open_dir("test_long_name").unwrap();
+    // 255个UTF-16码元是允许的最大长度
+    let name = "a".repeat(255);
+    dir.create_file(&name).unwrap();
+    assert!(dir.list().unwrap().contains(&name));
+    // 21个目录项跨越多个扇区，删除后全部释放
+    dir.delete_file(&name).unwrap();
+    let dir = root.open_dir("test_long_name").unwrap();
+    assert!(!dir.list().unwrap().contains(&name));
+    dir.create_file(&name).unwrap();
+    let dir = root.open_dir("test_long_name").unwrap();
+    assert!(dir.list().unwrap().contains(&name));
+    let name = "a".repeat(256);
+    assert!(matches!(
+        dir.create_file(&name),
+        Err(OperationError::NameTooLong)
+    ));
+    // 代理对占用两个码元
+    let name = "😀".repeat(128);
+    assert!(matches!(
+        dir.create_dir(&name),
+        Err(OperationError::NameTooLong)
+    ));
+    for name in ["a:b", "a*", "what?", "a\\b", "a|b", "<a>", "\"a\"", "", ".", "..", "a.", "a "] {
+        assert!(
+            matches!(dir.create_file(name), Err(OperationError::InvalidName)),
+            "{name}"
+        );
+        assert!(
+            matches!(dir.create_dir(name), Err(OperationError::InvalidName)),
+            "{name}"
+        );
+    }
+    dir.create_file("valid").unwrap();
+    assert!(matches!(
+        dir.rename_file("valid", "in/valid"),
+        Err(OperationError::InvalidName)
+    ));
+    assert!(matches!(
+        dir.rename_file("valid", ".."),
+        Err(OperationError::InvalidName)
+    ));
+    assert!(dir.open("valid").is_ok());
+    println!("test_name_limit passed");
+}
+
+/// 重新挂载后长文件名仍然正确，长目录项的校验和不匹配时使用短文件名
+pub fn test11_remount(device: FakeDevice) {
+    let name = "文件名文件名文件名文件名文件名.txt";
+    let fat32 = Fat32::new(device.clone()).unwrap();
+    let root = fat32.root_dir();
+    let cluster = root.metadata("test_long_name").unwrap().start_cluster;
+    let dir = root.open_dir("test_long_name").unwrap();
+    let list = dir.list().unwrap();
+    assert!(list.contains(&name.to_string()));
+    assert!(list.contains(&"a".repeat(255)));
+    let alias = dir.metadata(name).unwrap().short_name;
+    // 卷标也写入了磁盘
+    assert_eq!(fat32.label().unwrap(), Some("FAT32 TEST".to_string()));
+    fat32.unmount().unwrap();
+
+    // 目录的前两项是.和..，之后是第一个创建的文件的两个长目录项
+    let mut bpb = [0u8; 512];
+    device.read(0, &mut bpb).unwrap();
+    let bytes_per_sector = u16::from_le_bytes([bpb[11], bpb[12]]) as usize;
+    let sectors_per_cluster = bpb[13] as usize;
+    let reserved = u16::from_le_bytes([bpb[14], bpb[15]]) as usize;
+    let fat_size = u32::from_le_bytes([bpb[36], bpb[37], bpb[38], bpb[39]]) as usize;
+    let sector =
+        reserved + bpb[16] as usize * fat_size + (cluster as usize - 2) * sectors_per_cluster;
+    let mut buf = vec![0u8; bytes_per_sector];
+    device.read(sector, &mut buf).unwrap();
+    assert_eq!(buf[3 * 32], 0x01);
+    buf[3 * 32 + 13] ^= 0xFF;
+    device.write(sector, &buf).unwrap();
+
+    let fat32 = Fat32::new(device).unwrap();
+    let dir = fat32.root_dir().open_dir("test_long_name").unwrap();
+    let list = dir.list().unwrap();
+    assert!(!list.contains(&name.to_string()));
+    assert!(list.contains(&alias));
+    let names = dir.iter().unwrap().map(|e| e.unwrap().name).collect::<Vec<_>>();
+    assert!(names.contains(&alias));
+    fat32.unmount().unwrap();
+    println!("test11_remount passed");
+}
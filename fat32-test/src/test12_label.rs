@@ -0,0 +1,52 @@
+use crate::device::FakeDevice;
+use fat32_trait::DirectoryLike;
+use mfat32::{BlockDevice, Fat32, OperationError};
+
+pub fn test12_label(fat32: &Fat32) {
+    test_set_label(fat32);
+    test_invalid_label(fat32);
+}
+
+/// 引导扇区中的卷标
+fn bpb_label() -> Vec<u8> {
+    let device = FakeDevice::new("./test.img");
+    let mut buf = [0u8; 512];
+    device.read(0, &mut buf).unwrap();
+    buf[0x47..0x47 + 11].to_vec()
+}
+
+fn test_set_label(fat32: &Fat32) {
+    assert_eq!(fat32.label().unwrap(), None);
+    fat32.set_label(Some("my disk")).unwrap();
+    assert_eq!(fat32.label().unwrap(), Some("MY DISK".to_string()));
+    fat32.sync().unwrap();
+    assert_eq!(bpb_label(), b"MY DISK    ");
+    // 卷标目录项不会出现在目录中
+    let root = fat32.root_dir();
+    assert!(!root.list().unwrap().iter().any(|name| name.contains("MY DISK")));
+    assert!(!root
+        .iter()
+        .unwrap()
+        .any(|entry| entry.unwrap().name.contains("MY DISK")));
+    // 修改已有的卷标
+    fat32.set_label(Some("DATA")).unwrap();
+    assert_eq!(fat32.label().unwrap(), Some("DATA".to_string()));
+    // 删除卷标
+    fat32.set_label(None).unwrap();
+    assert_eq!(fat32.label().unwrap(), None);
+    fat32.sync().unwrap();
+    assert_eq!(bpb_label(), b"NO NAME    ");
+    fat32.set_label(Some("FAT32 TEST")).unwrap();
+    println!("test_set_label passed");
+}
+
+fn test_invalid_label(fat32: &Fat32) {
+    for label in ["a.b", "a*b", "too long label", ""] {
+        assert!(matches!(
+            fat32.set_label(Some(label)),
+            Err(OperationError::InvalidName)
+        ));
+    }
+    assert_eq!(fat32.label().unwrap(), Some("FAT32 TEST".to_string()));
+    println!("test_invalid_label passed");
+}
@@ -0,0 +1,139 @@
+use crate::device::FakeDevice;
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{Dir, Fat32, OperationError};
+
+use std::sync::Arc;
+
+pub fn test13_move(root: Arc<Dir>) {
+    test_move_file(root.clone());
+    test_move_dir(root.clone());
+    test_replace(root.clone());
+}
+
+/// 目录中..目录项的簇号
+fn dotdot_cluster(dir: &Dir) -> u32 {
+    dir.iter()
+        .unwrap()
+        .map(|e| e.unwrap())
+        .find(|e| e.name == "..")
+        .unwrap()
+        .start_cluster
+}
+
+fn test_move_file(root: Arc<Dir>) {
+    let base = root.create_dir_all("test_move/a").unwrap();
+    root.create_dir_all("test_move/b").unwrap();
+    let file = base.create_file_path("x.txt").unwrap();
+    file.write(0, b"move me").unwrap();
+    let start_cluster = file.metadata().unwrap().start_cluster;
+    let a = root.open_dir_path("test_move/a").unwrap();
+    let b = root.open_dir_path("test_move/b").unwrap();
+    a.rename("x.txt", &b, "Moved File.txt").unwrap();
+    assert!(a.open("x.txt").is_err());
+    let moved = b.open_file("moved file.txt").unwrap();
+    assert_eq!(moved.read(0, 7).unwrap(), b"move me");
+    // 只移动目录项，数据不会被复制
+    assert_eq!(moved.metadata().unwrap().start_cluster, start_cluster);
+    // 重新从磁盘读取
+    let b = root.open_dir_path("test_move/b").unwrap();
+    assert!(b.list().unwrap().contains(&"Moved File.txt".to_string()));
+    let a = root.open_dir_path("test_move/a").unwrap();
+    assert!(!a.list().unwrap().contains(&"x.txt".to_string()));
+    // 文件不能替换目录
+    a.create_dir("dir").unwrap();
+    assert!(matches!(
+        b.rename("Moved File.txt", &a, "DIR"),
+        Err(OperationError::DirExist)
+    ));
+    println!("test_move_file passed");
+}
+
+fn test_move_dir(root: Arc<Dir>) {
+    let a = root.open_dir_path("test_move/a").unwrap();
+    let b = root.open_dir_path("test_move/b").unwrap();
+    let sub = a.create_dir_all("sub/inner").unwrap();
+    sub.create_file("data").unwrap();
+    a.rename("sub", &b, "sub2").unwrap();
+    let b_cluster = root
+        .open_dir("test_move")
+        .unwrap()
+        .metadata("b")
+        .unwrap()
+        .start_cluster;
+    let sub2 = b.open_dir("sub2").unwrap();
+    assert_eq!(dotdot_cluster(&sub2), b_cluster);
+    assert!(root.exists("test_move/b/sub2/inner/data").unwrap());
+    assert!(!root.exists("test_move/a/sub").unwrap());
+    // ..指向新的父目录
+    let parent = root.open_dir_path("test_move/b/sub2/inner/../..").unwrap();
+    assert!(parent.list().unwrap().contains(&"sub2".to_string()));
+    // 在根目录中创建的目录..的簇号为0
+    let test_move = root.open_dir("test_move").unwrap();
+    assert_eq!(dotdot_cluster(&test_move), 0);
+    assert_eq!(dotdot_cluster(&b), root.metadata("test_move").unwrap().start_cluster);
+    // 移动到根目录时..的簇号为0
+    b.rename("sub2", &root, "test_move_sub").unwrap();
+    assert_eq!(dotdot_cluster(&root.open_dir("test_move_sub").unwrap()), 0);
+    root.rename("test_move_sub", &b, "sub2").unwrap();
+    // 不能移动到自己或者自己的子目录中
+    let inner = root.open_dir_path("test_move/b/sub2/inner").unwrap();
+    let test_move = root.open_dir("test_move").unwrap();
+    assert!(matches!(
+        root.rename("test_move", &inner, "loop"),
+        Err(OperationError::InvalidMove)
+    ));
+    assert!(matches!(
+        test_move.rename("b", &root.open_dir_path("test_move/b").unwrap(), "b"),
+        Err(OperationError::InvalidMove)
+    ));
+    assert!(root.exists("test_move/b/sub2/inner").unwrap());
+    println!("test_move_dir passed");
+}
+
+fn test_replace(root: Arc<Dir>) {
+    let a = root.open_dir_path("test_move/a").unwrap();
+    let b = root.open_dir_path("test_move/b").unwrap();
+    a.create_file("new.txt").unwrap();
+    a.open("new.txt").unwrap().write(0, b"new").unwrap();
+    b.create_file("old.txt").unwrap();
+    b.open("old.txt").unwrap().write(0, b"old content").unwrap();
+    a.rename("new.txt", &b, "OLD.TXT").unwrap();
+    let b = root.open_dir_path("test_move/b").unwrap();
+    let names = b.list().unwrap();
+    assert!(names.contains(&"OLD.TXT".to_string()));
+    assert!(!names.contains(&"old.txt".to_string()));
+    assert_eq!(b.open("old.txt").unwrap().read(0, 16).unwrap(), b"new");
+    // 只能替换空目录
+    a.create_dir("empty").unwrap();
+    b.create_dir("empty").unwrap();
+    b.create_dir("full").unwrap();
+    b.open_dir("full").unwrap().create_file("f").unwrap();
+    assert!(matches!(
+        a.rename("empty", &b, "full"),
+        Err(OperationError::DirNotEmpty)
+    ));
+    a.rename("empty", &b, "empty").unwrap();
+    assert!(!a.list().unwrap().contains(&"empty".to_string()));
+    // 同一个目录中重命名
+    b.rename("full", &b, "Full Dir").unwrap();
+    assert!(b.exists("Full Dir/f").unwrap());
+    b.rename("Full Dir", &b, "FULL DIR").unwrap();
+    assert!(b.list().unwrap().contains(&"FULL DIR".to_string()));
+    println!("test_replace passed");
+}
+
+/// 重新挂载后移动的结果仍然存在
+pub fn test13_remount(device: FakeDevice) {
+    let fat32 = Fat32::new(device).unwrap();
+    assert!(fat32.exists("test_move/b/sub2/inner/data").unwrap());
+    assert!(fat32.exists("test_move/b/Moved File.txt").unwrap());
+    assert!(!fat32.exists("test_move/a/x.txt").unwrap());
+    let test_move = fat32.open_dir_path("test_move").unwrap();
+    let sub2 = fat32.open_dir_path("test_move/b/sub2").unwrap();
+    assert_eq!(
+        dotdot_cluster(&sub2),
+        test_move.metadata("b").unwrap().start_cluster
+    );
+    fat32.unmount().unwrap();
+    println!("test13_remount passed");
+}
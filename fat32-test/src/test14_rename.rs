@@ -0,0 +1,58 @@
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::Dir;
+
+use std::sync::Arc;
+
+pub fn test14_rename(root: Arc<Dir>) {
+    test_rename_keep_metadata(root.clone());
+    test_rename_reuse_short_name(root.clone());
+}
+
+fn test_rename_keep_metadata(root: Arc<Dir>) {
+    root.create_dir("test_rename_meta").unwrap();
+    let dir = root.open_dir("test_rename_meta").unwrap();
+    dir.create_file("data.bin").unwrap();
+    dir.open("data.bin").unwrap().write(0, &[7u8; 1000]).unwrap();
+    let before = dir.metadata("data.bin").unwrap();
+    dir.rename_file("data.bin", "renamed data.bin").unwrap();
+    // 重新从磁盘读取
+    let dir = root.open_dir("test_rename_meta").unwrap();
+    let after = dir.metadata("renamed data.bin").unwrap();
+    assert_eq!(after.size, 1000);
+    assert_eq!(after.start_cluster, before.start_cluster);
+    assert_eq!(after.attr, before.attr);
+    assert_eq!(after.created, before.created);
+    assert_eq!(after.modified, before.modified);
+    assert_eq!(
+        dir.open("renamed data.bin").unwrap().read(0, 1000).unwrap(),
+        vec![7u8; 1000]
+    );
+    // 目录同样保留原来的信息
+    dir.create_dir("sub").unwrap();
+    dir.open_dir("sub").unwrap().create_file("inner").unwrap();
+    let before = dir.metadata("sub").unwrap();
+    dir.rename_dir("sub", "sub dir").unwrap();
+    let dir = root.open_dir("test_rename_meta").unwrap();
+    let after = dir.metadata("sub dir").unwrap();
+    assert_eq!(after.created, before.created);
+    assert_eq!(after.start_cluster, before.start_cluster);
+    assert!(dir.exists("sub dir/inner").unwrap());
+    println!("test_rename_keep_metadata passed");
+}
+
+fn test_rename_reuse_short_name(root: Arc<Dir>) {
+    let dir = root.open_dir("test_rename_meta").unwrap();
+    dir.create_file("readme.txt").unwrap();
+    dir.rename_file("readme.txt", "README.TXT").unwrap();
+    // 只改变大小写时可以继续使用原来的短文件名
+    assert_eq!(dir.metadata("README.TXT").unwrap().short_name, "README.TXT");
+    let entries = dir
+        .iter()
+        .unwrap()
+        .map(|e| e.unwrap())
+        .filter(|e| e.short_name.starts_with("README"))
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "README.TXT");
+    println!("test_rename_reuse_short_name passed");
+}
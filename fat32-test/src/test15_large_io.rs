@@ -0,0 +1,79 @@
+use crate::device::FakeDevice;
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{CacheClass, CacheConfig, CacheOptions, CachePolicy, Dir, Fat32, MountOptions};
+
+use std::sync::Arc;
+
+pub fn test15_large_io(root: Arc<Dir>) {
+    test_large_read_write(root.clone());
+    test_mixed_small_and_large(root.clone());
+}
+
+fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| ((i * 7 + seed) % 251) as u8).collect()
+}
+
+fn test_large_read_write(root: Arc<Dir>) {
+    root.create_file("test_large_io").unwrap();
+    let file = root.open("test_large_io").unwrap();
+    // 跨越多个簇，且首尾不与扇区对齐
+    let data = pattern(300 * 1024 + 123, 1);
+    assert_eq!(file.write(100, &data).unwrap(), data.len() as u64);
+    let content = file.read(100, data.len() as u64).unwrap();
+    assert_eq!(content, data);
+    // 从中间任意位置读取
+    let content = file.read(5000, 70000).unwrap();
+    assert_eq!(content, data[4900..74900]);
+    assert_eq!(file.read(0, 100).unwrap(), vec![0u8; 100]);
+    println!("test_large_read_write passed");
+}
+
+fn test_mixed_small_and_large(root: Arc<Dir>) {
+    let file = root.open("test_large_io").unwrap();
+    // 小写入经过缓存，随后的大块读取需要看到它
+    file.write(8192 + 3, &[0xAA; 10]).unwrap();
+    let content = file.read(0, 64 * 1024).unwrap();
+    assert_eq!(content[8192 + 3..8192 + 13], [0xAA; 10]);
+    // 大块写入覆盖缓存中的扇区，随后的小读取需要看到新内容
+    let data = pattern(64 * 1024, 9);
+    file.write(0, &data).unwrap();
+    assert_eq!(file.read(8192, 16).unwrap(), data[8192..8208]);
+    assert_eq!(file.read(0, 64 * 1024).unwrap(), data);
+    println!("test_mixed_small_and_large passed");
+}
+
+/// 使用很小的缓存重新挂载，频繁换出后内容仍然正确
+pub fn test15_remount(device: FakeDevice) {
+    let options = MountOptions {
+        cache: CacheOptions {
+            data: CacheConfig::new(4, CachePolicy::Fifo),
+            ..CacheOptions::with_capacity(8)
+        },
+        ..Default::default()
+    };
+    let fat32 = Fat32::with_options(device, options).unwrap();
+    let root = fat32.root_dir();
+    let file = root.open("test_large_io").unwrap();
+    let data = pattern(64 * 1024, 9);
+    assert_eq!(file.read(0, 64 * 1024).unwrap(), data);
+    // 不足一个扇区的小块写入，全部经过缓存
+    for i in 0..64 {
+        file.write(i * 100 + 1, &[i as u8; 100]).unwrap();
+    }
+    for i in 0..64 {
+        assert_eq!(file.read(i * 100 + 1, 100).unwrap(), vec![i as u8; 100]);
+    }
+    root.create_dir("test_small_cache").unwrap();
+    assert!(root.open_dir("test_small_cache").is_ok());
+    // 顺序读取整个文件不会换出fat扇区
+    fat32.reset_cache_stats();
+    for i in 0..64 {
+        file.read(i * 1000, 1000).unwrap();
+    }
+    let stats = fat32.cache_stats(CacheClass::Fat);
+    assert_eq!(stats.misses, 0);
+    assert!(stats.hits > 0);
+    assert!(fat32.cache_stats(CacheClass::Data).evictions > 0);
+    fat32.unmount().unwrap();
+    println!("test15_remount passed");
+}
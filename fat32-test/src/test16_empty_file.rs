@@ -0,0 +1,95 @@
+use crate::device::FakeDevice;
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{Dir, Fat32};
+
+use std::sync::Arc;
+
+pub fn test16_empty_file(root: Arc<Dir>) {
+    root.create_file("ZLEN8.BIN").unwrap();
+    root.create_file("ZLEN10.BIN").unwrap();
+    test_empty_write(root.clone());
+    println!("test16_empty_file passed");
+}
+
+/// 模拟其它系统创建的空文件：目录项中的起始簇号为0，且没有占用簇
+/// 需要在卸载之后直接修改镜像
+pub fn test16_detach() {
+    detach_cluster(&[b"ZLEN8   BIN", b"ZLEN10  BIN"]);
+}
+
+/// 写入0个字节不改变文件
+fn test_empty_write(root: Arc<Dir>) {
+    let file = root.open("ZLEN10.BIN").unwrap();
+    assert_eq!(file.write_at(4096, &[]).unwrap(), 0);
+    assert_eq!(file.size().unwrap(), 0);
+}
+
+/// 在根目录中找到这些短文件名，清除它们的簇号并释放簇
+fn detach_cluster(short_names: &[&[u8; 11]]) {
+    let mut img = std::fs::read("./test.img").unwrap();
+    let u16_at = |img: &[u8], at: usize| u16::from_le_bytes([img[at], img[at + 1]]) as usize;
+    let u32_at = |img: &[u8], at: usize| u32::from_le_bytes(img[at..at + 4].try_into().unwrap());
+    let bytes_per_sector = u16_at(&img, 11);
+    let bytes_per_cluster = img[13] as usize * bytes_per_sector;
+    let reserved = u16_at(&img, 14);
+    let fats = img[16] as usize;
+    let sectors_per_fat = u32_at(&img, 36) as usize;
+    let fs_info = u16_at(&img, 48) * bytes_per_sector;
+    let fat_at = |i: usize, cluster: usize| {
+        (reserved + i * sectors_per_fat) * bytes_per_sector + cluster * 4
+    };
+    let data = (reserved + fats * sectors_per_fat) * bytes_per_sector;
+    let mut free = u32_at(&img, fs_info + 488);
+    let mut found = 0;
+    let mut cluster = u32_at(&img, 44) as usize;
+    while cluster < 0x0FFFFFF8 {
+        let start = data + (cluster - 2) * bytes_per_cluster;
+        for pos in (start..start + bytes_per_cluster).step_by(32) {
+            if !short_names.iter().any(|name| &img[pos..pos + 11] == *name) {
+                continue;
+            }
+            let first = u16_at(&img, pos + 20) << 16 | u16_at(&img, pos + 26);
+            assert!(first >= 2);
+            img[pos + 20..pos + 22].fill(0);
+            img[pos + 26..pos + 28].fill(0);
+            for i in 0..fats {
+                let at = fat_at(i, first);
+                img[at..at + 4].fill(0);
+            }
+            free += 1;
+            found += 1;
+        }
+        cluster = (u32_at(&img, fat_at(0, cluster)) & 0x0FFFFFFF) as usize;
+    }
+    assert_eq!(found, short_names.len());
+    img[fs_info + 488..fs_info + 492].copy_from_slice(&free.to_le_bytes());
+    std::fs::write("./test.img", img).unwrap();
+}
+
+pub fn test16_remount(device: FakeDevice) {
+    let fat32 = Fat32::new(device.clone()).unwrap();
+    let root = fat32.root_dir();
+    assert_eq!(root.metadata("ZLEN8.BIN").unwrap().start_cluster, 0);
+    let file = root.open("ZLEN8.BIN").unwrap();
+    file.set_len(4096).unwrap();
+    assert!(root.metadata("ZLEN8.BIN").unwrap().start_cluster >= 2);
+    let file = root.open("ZLEN10.BIN").unwrap();
+    assert_eq!(file.write_at(0, &[]).unwrap(), 0);
+    assert_eq!(root.metadata("ZLEN10.BIN").unwrap().start_cluster, 0);
+    assert_eq!(file.write_at(5000, b"hello").unwrap(), 5);
+    assert!(root.metadata("ZLEN10.BIN").unwrap().start_cluster >= 2);
+    fat32.unmount().unwrap();
+    let fat32 = Fat32::new(device).unwrap();
+    let root = fat32.root_dir();
+    let file = root.open("ZLEN8.BIN").unwrap();
+    assert_eq!(file.size().unwrap(), 4096);
+    assert_eq!(file.read(0, 8192).unwrap(), vec![0; 4096]);
+    assert!(root.metadata("ZLEN8.BIN").unwrap().start_cluster >= 2);
+    let file = root.open("ZLEN10.BIN").unwrap();
+    let mut buf = [0xff; 5005];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 5005);
+    assert_eq!(buf[..5000], [0; 5000]);
+    assert_eq!(&buf[5000..], b"hello");
+    fat32.unmount().unwrap();
+    println!("test16_remount passed");
+}
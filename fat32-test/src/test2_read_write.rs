@@ -11,6 +11,9 @@ pub fn test2_read_write(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
     test_read_multi_thread(root.clone());
     test_write_multi_thread(root.clone());
     test_clear_file(root.clone());
+    test_set_len(root.clone());
+    test_write_too_large(root.clone());
+    test_read_write_at(root.clone());
 }
 
 fn test_read_empty_file(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
@@ -120,13 +123,76 @@ fn test_write_multi_thread(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>
 }
 
 fn test_clear_file(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
-    let _ans = root.create_file("test_clear_file").unwrap();
+    root.create_file("test_clear_file").unwrap();
     let test_clear_file = root.open("test_clear_file");
     assert!(test_clear_file.is_ok());
     let test_clear_file = test_clear_file.unwrap();
     test_clear_file.write(0, &[0x12; 512]);
-    test_clear_file.clear();
+    test_clear_file.clear().unwrap();
     let content = test_clear_file.read(0, 512).unwrap();
     assert_eq!(content.len(), 0);
     println!("test_clear_file passed");
 }
+
+fn test_set_len(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
+    root.create_file("test_set_len").unwrap();
+    let file = root.open("test_set_len").unwrap();
+    file.write(0, &[0x12; 1000]).unwrap();
+    file.truncate(10).unwrap();
+    assert_eq!(file.size().unwrap(), 10);
+    assert_eq!(file.read(0, 1000).unwrap(), [0x12; 10]);
+    file.set_len(512 * 10).unwrap();
+    assert_eq!(file.size().unwrap(), 512 * 10);
+    let content = file.read(0, 512 * 10).unwrap();
+    assert_eq!(content[..10], [0x12; 10]);
+    assert!(content[10..].iter().all(|&x| x == 0));
+    file.set_len(0).unwrap();
+    assert_eq!(file.read(0, 512).unwrap().len(), 0);
+    println!("test_set_len passed");
+}
+
+fn test_write_too_large(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
+    root.create_file("test_write_too_large").unwrap();
+    let file = root.open("test_write_too_large").unwrap();
+    assert!(file.write(u32::MAX as u64, &[1]).is_err());
+    assert!(file.write(u64::MAX, &[1]).is_err());
+    assert!(file.set_len(u32::MAX as u64 + 1).is_err());
+    assert_eq!(file.size().unwrap(), 0);
+    println!("test_write_too_large passed");
+}
+
+fn test_read_write_at(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
+    root.create_file("test_read_write_at").unwrap();
+    let file = root.open("test_read_write_at").unwrap();
+    let data: Vec<u8> = (0..512 * 5).map(|x| x as u8).collect();
+    assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+    // 跨扇区读取
+    let mut buf = [0; 600];
+    assert_eq!(file.read_at(300, &mut buf).unwrap(), 600);
+    assert_eq!(buf[..], data[300..900]);
+    // 读到文件末尾为止
+    let mut buf = [0; 512];
+    assert_eq!(file.read_at(512 * 5 - 100, &mut buf).unwrap(), 100);
+    assert_eq!(buf[..100], data[512 * 5 - 100..]);
+    assert_eq!(file.read_at(512 * 5, &mut buf).unwrap(), 0);
+    // 在文件末尾之后写入
+    assert_eq!(file.write_at(512 * 6 + 7, &[0x34; 20]).unwrap(), 20);
+    assert_eq!(file.size().unwrap(), 512 * 6 + 27);
+    let mut buf = [0xff; 30];
+    assert_eq!(file.read_at(512 * 6, &mut buf).unwrap(), 27);
+    assert_eq!(buf[7..27], [0x34; 20]);
+    // 空洞读出来都是0，即使簇是其它文件释放后重新分配的
+    assert_eq!(buf[..7], [0; 7]);
+    root.create_file("test_write_at_old").unwrap();
+    let old = root.open("test_write_at_old").unwrap();
+    old.write_at(0, &[0x56; 8192]).unwrap();
+    root.delete_file("test_write_at_old").unwrap();
+    root.create_file("test_write_at_gap").unwrap();
+    let file = root.open("test_write_at_gap").unwrap();
+    file.write_at(8000, &[0x78; 10]).unwrap();
+    let mut buf = [0xff; 8010];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 8010);
+    assert!(buf[..8000].iter().all(|&b| b == 0));
+    assert_eq!(buf[8000..], [0x78; 10]);
+    println!("test_read_write_at passed");
+}
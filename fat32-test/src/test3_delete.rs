@@ -7,6 +7,7 @@ use std::sync::Arc;
 pub fn test_delete_file_and_dir(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
     test_delete_file(root.clone());
     test_delete_dir(root.clone());
+    test_reuse_cluster_for_dir(root.clone());
 }
 
 fn test_delete_file(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
@@ -55,3 +56,18 @@ fn test_delete_dir(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
     assert!(file_and_dir.contains(&"..".to_string()));
     println!("test_delete_dir passed");
 }
+
+fn test_reuse_cluster_for_dir(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
+    // 删除文件后，新目录会重新使用文件的簇，其中残留着文件的内容
+    root.create_file("test_reuse_file").unwrap();
+    let file = root.open("test_reuse_file").unwrap();
+    file.write(0, &[b'A'; 8192]).unwrap();
+    root.delete_file("test_reuse_file").unwrap();
+    root.create_dir("test_reuse_dir").unwrap();
+    let dir = root.cd("test_reuse_dir").unwrap();
+    let mut names = dir.list().unwrap();
+    names.sort();
+    assert_eq!(names, [".", ".."]);
+    root.delete_dir("test_reuse_dir").unwrap();
+    println!("test_reuse_cluster_for_dir passed");
+}
@@ -11,7 +11,7 @@ pub fn test_rename(root: Arc<dyn DirectoryLike<Error: Error  + 'static>>) {
     root.create_file("test.txt").unwrap();
     let a = root.rename_file("test.txt", "newtest.txt");
     assert!(a.is_ok());
-    let _a = root.create_dir("test_dir").unwrap();
+    root.create_dir("test_dir").unwrap();
     let a = root.rename_dir("test_dir", "new_test_dir");
     assert!(a.is_ok());
     let names = root.list().unwrap();
@@ -0,0 +1,67 @@
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{DateTime, Dir, StdTimeProvider, TimeProvider};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// 每次调用前进3秒的时钟
+#[derive(Debug)]
+pub struct StepClock {
+    millis: AtomicU64,
+}
+
+impl StepClock {
+    pub fn new() -> Self {
+        // 2023-04-15 13:45:31.250 UTC
+        Self {
+            millis: AtomicU64::new(1_681_566_331_250),
+        }
+    }
+}
+
+impl TimeProvider for StepClock {
+    fn now(&self) -> DateTime {
+        DateTime::from_unix_millis(self.millis.fetch_add(3000, Ordering::SeqCst))
+    }
+}
+
+pub fn test5_time(root: Arc<Dir>) {
+    test_create_time(root.clone());
+    test_modify_time(root.clone());
+    test_std_time();
+}
+
+fn test_create_time(root: Arc<Dir>) {
+    root.create_file("test_create_time").unwrap();
+    let file = root.open_file("test_create_time").unwrap();
+    let created = file.created().unwrap();
+    assert!(created.year >= 2023);
+    // 创建时间精确到10ms
+    assert_eq!(created.millisecond, 250);
+    assert_eq!(file.accessed().unwrap().day, created.day);
+    let modified = file.modified().unwrap();
+    assert_eq!(modified.second, created.second / 2 * 2);
+    println!("test_create_time passed");
+}
+
+fn test_modify_time(root: Arc<Dir>) {
+    root.create_file("test_modify_time").unwrap();
+    let file = root.open_file("test_modify_time").unwrap();
+    let created = file.created().unwrap();
+    let before = file.modified().unwrap();
+    file.write(0, &[1, 2, 3]).unwrap();
+    let after = file.modified().unwrap();
+    assert!(after > before);
+    // 写入不会改变创建时间
+    assert_eq!(file.created().unwrap(), created);
+    file.set_len(0).unwrap();
+    assert!(file.modified().unwrap() > after);
+    println!("test_modify_time passed");
+}
+
+fn test_std_time() {
+    let now = StdTimeProvider.now();
+    assert!(now.year >= 2024);
+    assert!((1..=12).contains(&now.month));
+    println!("test_std_time passed");
+}
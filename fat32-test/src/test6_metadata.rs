@@ -0,0 +1,40 @@
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{Dir, EntryFlags};
+
+use std::sync::Arc;
+
+pub fn test6_metadata(root: Arc<Dir>) {
+    test_file_metadata(root.clone());
+    test_dir_metadata(root.clone());
+}
+
+fn test_file_metadata(root: Arc<Dir>) {
+    root.create_file("test_file_metadata.txt").unwrap();
+    let file = root.open_file("test_file_metadata.txt").unwrap();
+    file.write(0, &[0x12; 1000]).unwrap();
+    let meta = file.metadata().unwrap();
+    assert!(meta.is_file());
+    assert!(meta.attr.contains(EntryFlags::ARCHIVE));
+    assert_eq!(meta.size, 1000);
+    let bytes_per_cluster = meta.allocated_size / meta.cluster_count as u64;
+    assert_eq!(meta.cluster_count as u64, 1000u64.div_ceil(bytes_per_cluster));
+    assert!(meta.start_cluster >= 2);
+    assert!(meta.short_name.contains('~'));
+    assert!(meta.short_name.ends_with(".TXT"));
+    assert_eq!(meta.created, file.created().unwrap());
+    let by_name = root.metadata("test_file_metadata.txt").unwrap();
+    assert_eq!(by_name.start_cluster, meta.start_cluster);
+    assert_eq!(by_name.size, meta.size);
+    assert!(root.metadata("no_such_file").is_err());
+    println!("test_file_metadata passed");
+}
+
+fn test_dir_metadata(root: Arc<Dir>) {
+    root.create_dir("meta_dir").unwrap();
+    let meta = root.metadata("meta_dir").unwrap();
+    assert!(meta.is_dir());
+    assert_eq!(meta.cluster_count, 1);
+    assert_eq!(meta.size, meta.allocated_size);
+    assert_eq!(meta.short_name, "META_DIR");
+    println!("test_dir_metadata passed");
+}
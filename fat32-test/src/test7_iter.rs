@@ -0,0 +1,60 @@
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::{Dir, DirEntryKind};
+
+use std::sync::Arc;
+
+pub fn test7_iter(root: Arc<Dir>) {
+    test_iter_kind(root.clone());
+    test_iter_resume(root.clone());
+}
+
+fn test_iter_kind(root: Arc<Dir>) {
+    root.create_dir("test_iter").unwrap();
+    let dir = root.cd("test_iter").unwrap();
+    dir.create_file("a_long_file_name.txt").unwrap();
+    dir.create_dir("sub").unwrap();
+    dir.open("a_long_file_name.txt")
+        .unwrap()
+        .write(0, &[1; 100])
+        .unwrap();
+    let dir = root.open_dir("test_iter").unwrap();
+    let entries = dir.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+    // 按照磁盘上的顺序
+    assert_eq!(names, [".", "..", "a_long_file_name.txt", "sub"]);
+    assert_eq!(entries[2].kind, DirEntryKind::File);
+    assert_eq!(entries[2].size, 100);
+    assert!(entries[2].short_name.ends_with(".TXT"));
+    assert!(entries[3].is_dir());
+    println!("test_iter_kind passed");
+}
+
+fn test_iter_resume(root: Arc<Dir>) {
+    root.create_dir("test_iter_resume").unwrap();
+    let dir = root.open_dir("test_iter_resume").unwrap();
+    // 目录项超过一个簇
+    for i in 0..100 {
+        dir.create_file(&format!("resume_file_{i}")).unwrap();
+    }
+    let all = dir.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(all.len(), 102);
+    // 每次只读取几个，然后从cookie继续
+    let mut names = Vec::new();
+    let mut cookie = 0;
+    loop {
+        let batch = dir
+            .iter_from(cookie)
+            .unwrap()
+            .take(7)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        if batch.is_empty() {
+            break;
+        }
+        cookie = batch.last().unwrap().next_offset;
+        names.extend(batch.into_iter().map(|e| e.name));
+    }
+    let expect = all.into_iter().map(|e| e.name).collect::<Vec<_>>();
+    assert_eq!(names, expect);
+    println!("test_iter_resume passed");
+}
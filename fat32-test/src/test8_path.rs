@@ -0,0 +1,83 @@
+use fat32_trait::FileLike;
+use mfat32::{Dir, OperationError};
+
+use std::sync::Arc;
+
+pub fn test8_path(root: Arc<Dir>) {
+    test_create_dir_all(root.clone());
+    test_open_path(root.clone());
+    test_relative_path(root.clone());
+    test_remove_path(root.clone());
+    test_path_error(root.clone());
+}
+
+fn test_create_dir_all(root: Arc<Dir>) {
+    root.create_dir_all("/path_a/path_b//path_c/").unwrap();
+    assert!(root.exists("/path_a/path_b/path_c").unwrap());
+    // 已经存在的目录不会报错
+    root.create_dir_all("path_a/./path_b").unwrap();
+    assert!(root.exists("PATH_A/Path_B").unwrap());
+    assert!(!root.exists("/path_a/no_dir").unwrap());
+    assert!(!root.exists("/no_dir/path_b").unwrap());
+    assert!(root.exists("/").unwrap());
+    println!("test_create_dir_all passed");
+}
+
+fn test_open_path(root: Arc<Dir>) {
+    let file = root.create_file_path("/path_a/path_b/file.txt").unwrap();
+    file.write(0, b"hello").unwrap();
+    let file = root.open_path("//PATH_A/path_b/../path_b/./FILE.TXT").unwrap();
+    assert_eq!(file.read(0, 5).unwrap(), b"hello");
+    // 根目录的..仍是根目录
+    assert!(root.open_path("/../../path_a/path_b/file.txt").is_ok());
+    assert!(root.open_path("/path_a/path_b/").is_err());
+    assert!(root.create_file_path("/path_a/path_b/file.txt").is_err());
+    println!("test_open_path passed");
+}
+
+fn test_relative_path(root: Arc<Dir>) {
+    let dir = root.open_dir_path("path_a/path_b/path_c").unwrap();
+    assert!(dir.open_path("../file.txt").is_ok());
+    // 子目录中的绝对路径从根目录开始
+    assert!(dir.open_path("/path_a/path_b/file.txt").is_ok());
+    let dir = dir.open_dir_path("../../..").unwrap();
+    assert!(dir.exists("path_a").unwrap());
+    println!("test_relative_path passed");
+}
+
+fn test_remove_path(root: Arc<Dir>) {
+    root.remove_path("/path_a/path_b/file.txt").unwrap();
+    assert!(!root.exists("/path_a/path_b/file.txt").unwrap());
+    root.remove_path("/Path_A").unwrap();
+    assert!(!root.exists("/path_a").unwrap());
+    assert!(root.remove_path("/").is_err());
+    // 通过..删除后，原来的目录中也看不到被删除的项
+    root.create_dir_all("/path_x").unwrap();
+    root.create_file_path("/path_f").unwrap();
+    root.remove_path("path_x/../path_f").unwrap();
+    assert!(!root.exists("/path_f").unwrap());
+    assert!(root.open_path("/path_f").is_err());
+    let dir = root.open_dir_path("/path_x").unwrap();
+    root.create_file_path("/path_x/path_g").unwrap();
+    dir.remove_path("../path_x/path_g").unwrap();
+    assert!(!dir.exists("path_g").unwrap());
+    root.remove_path("/path_x").unwrap();
+    println!("test_remove_path passed");
+}
+
+fn test_path_error(root: Arc<Dir>) {
+    root.create_dir_all("/path_err").unwrap();
+    root.create_file_path("/path_err/file").unwrap();
+    match root.open_path("/path_err/missing/file") {
+        Err(OperationError::Path { component, .. }) => assert_eq!(component, "missing"),
+        other => panic!("unexpected {:?}", other),
+    }
+    match root.create_dir_all("/path_err/file/sub") {
+        Err(OperationError::Path { component, error }) => {
+            assert_eq!(component, "file");
+            assert!(matches!(*error, OperationError::FileExist));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    println!("test_path_error passed");
+}
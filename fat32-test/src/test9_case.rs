@@ -0,0 +1,59 @@
+use fat32_trait::{DirectoryLike, FileLike};
+use mfat32::Dir;
+
+use std::sync::Arc;
+
+pub fn test9_case(root: Arc<Dir>) {
+    test_case_lookup(root.clone());
+    test_short_name_alias(root.clone());
+    test_rename_case(root.clone());
+}
+
+fn test_case_lookup(root: Arc<Dir>) {
+    root.create_dir("test_case").unwrap();
+    let dir = root.open_dir("test_case").unwrap();
+    dir.create_file("readme.txt").unwrap();
+    dir.open("README.TXT").unwrap().write(0, b"fat").unwrap();
+    assert_eq!(dir.open("Readme.Txt").unwrap().read(0, 3).unwrap(), b"fat");
+    // 只有大小写不同的文件不能同时存在
+    assert!(dir.create_file("README.TXT").is_err());
+    dir.create_dir("SubDir").unwrap();
+    assert!(dir.create_dir("subdir").is_err());
+    assert!(dir.cd("SUBDIR").is_ok());
+    // 保留创建时的大小写
+    let names = dir.list().unwrap();
+    assert!(names.contains(&"readme.txt".to_string()));
+    assert!(names.contains(&"SubDir".to_string()));
+    dir.delete_file("ReadMe.TXT").unwrap();
+    assert!(dir.open("readme.txt").is_err());
+    println!("test_case_lookup passed");
+}
+
+fn test_short_name_alias(root: Arc<Dir>) {
+    let dir = root.open_dir("test_case").unwrap();
+    dir.create_dir("Program Files").unwrap();
+    let alias = dir.metadata("Program Files").unwrap().short_name;
+    assert!(alias.contains('~'));
+    assert!(dir.cd(&alias).is_ok());
+    assert!(dir.cd(&alias.to_lowercase()).is_ok());
+    dir.create_file("long_file_name.txt").unwrap();
+    let alias = dir.metadata("long_file_name.txt").unwrap().short_name;
+    assert!(dir.open(&alias).is_ok());
+    println!("test_short_name_alias passed");
+}
+
+fn test_rename_case(root: Arc<Dir>) {
+    let dir = root.open_dir("test_case").unwrap();
+    dir.create_file("rename.txt").unwrap();
+    dir.create_file("other.txt").unwrap();
+    assert!(dir.rename_file("RENAME.TXT", "Other.txt").is_err());
+    dir.rename_file("RENAME.TXT", "Rename.TXT").unwrap();
+    let names = dir.list().unwrap();
+    assert!(names.contains(&"Rename.TXT".to_string()));
+    assert!(!names.contains(&"rename.txt".to_string()));
+    // 重命名后仍然可以正常写入
+    dir.open("rename.txt").unwrap().write(0, b"abc").unwrap();
+    let dir = root.open_dir("test_case").unwrap();
+    assert_eq!(dir.open("Rename.TXT").unwrap().size().unwrap(), 3);
+    println!("test_rename_case passed");
+}
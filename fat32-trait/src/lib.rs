@@ -27,8 +27,18 @@ pub trait DirectoryLike: Debug + Send + Sync {
 
 pub trait FileLike: Debug + Send + Sync {
     type Error:  Error + 'static;
-    fn read(&self, offset: u32, size: u32) -> Result<Vec<u8>, Self::Error>;
-    fn write(&self, offset: u32, data: &[u8]) -> Result<u32, Self::Error>;
-    fn clear(&self);
-    fn size(&self) -> u32;
+    fn read(&self, offset: u64, size: u64) -> Result<Vec<u8>, Self::Error>;
+    fn write(&self, offset: u64, data: &[u8]) -> Result<u64, Self::Error>;
+    /// 从offset处读取数据到buf中，返回读取的字节数
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error>;
+    /// 将buf写入offset处，返回写入的字节数
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, Self::Error>;
+    fn clear(&self) -> Result<(), Self::Error>;
+    /// 将文件缩小或扩大到new_len，扩大的部分填充0
+    fn set_len(&self, new_len: u64) -> Result<(), Self::Error>;
+    /// 与set_len相同
+    fn truncate(&self, new_len: u64) -> Result<(), Self::Error> {
+        self.set_len(new_len)
+    }
+    fn size(&self) -> Result<u64, Self::Error>;
 }
@@ -14,7 +14,7 @@ fn main() {
     root.list().unwrap().iter().for_each(|name| {
         println!("{}", name);
     });
-    fat32.sync();
+    fat32.sync().unwrap();
 }
 
 #[derive(Debug, Clone)]
@@ -39,18 +39,18 @@ impl BlockDevice for FakeDevice {
     type Error = ();
     fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, ()> {
         let mut file = self.file.lock().unwrap();
-        file.seek(std::io::SeekFrom::Start(block as u64 * 512))
+        file.seek(std::io::SeekFrom::Start(block as u64 * buf.len() as u64))
             .unwrap();
-        file.read(buf).unwrap();
-        Ok(0)
+        file.read_exact(buf).unwrap();
+        Ok(buf.len())
     }
 
     fn write(&self, block: usize, buf: &[u8]) -> Result<usize, ()> {
         let mut file = self.file.lock().unwrap();
-        file.seek(std::io::SeekFrom::Start(block as u64 * 512))
+        file.seek(std::io::SeekFrom::Start(block as u64 * buf.len() as u64))
             .unwrap();
-        file.write(buf).unwrap();
-        Ok(0)
+        file.write_all(buf).unwrap();
+        Ok(buf.len())
     }
 
     fn flush(&self) -> Result<(), ()> {
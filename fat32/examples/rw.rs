@@ -11,15 +11,15 @@ fn main() {
     let ans = root.create_file("test.txt");
     println!("{ans:?}");
     let file = root.open("test.txt").unwrap();
-    file.clear(); // clear file
-    println!("file size:{}", file.size());
+    file.clear().unwrap(); // clear file
+    println!("file size:{}", file.size().unwrap());
     let txt = file.read(0, 100).unwrap();
     println!("txt: {}", core::str::from_utf8(txt.as_slice()).unwrap());
     let w = file.write(0, b"hello world");
     println!("{:?}", w);
     let txt = file.read(0, 20).unwrap();
     println!("txt: {}", core::str::from_utf8(txt.as_slice()).unwrap());
-    fat32.sync();
+    fat32.sync().unwrap();
 }
 
 #[derive(Debug, Clone)]
@@ -44,18 +44,18 @@ impl BlockDevice for FakeDevice {
     type Error = ();
     fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, ()> {
         let mut file = self.file.lock().unwrap();
-        file.seek(std::io::SeekFrom::Start(block as u64 * 512))
+        file.seek(std::io::SeekFrom::Start(block as u64 * buf.len() as u64))
             .unwrap();
-        file.read(buf).unwrap();
-        Ok(0)
+        file.read_exact(buf).unwrap();
+        Ok(buf.len())
     }
 
     fn write(&self, block: usize, buf: &[u8]) -> Result<usize, ()> {
         let mut file = self.file.lock().unwrap();
-        file.seek(std::io::SeekFrom::Start(block as u64 * 512))
+        file.seek(std::io::SeekFrom::Start(block as u64 * buf.len() as u64))
             .unwrap();
-        file.write(buf).unwrap();
-        Ok(0)
+        file.write_all(buf).unwrap();
+        Ok(buf.len())
     }
 
     fn flush(&self) -> Result<(), ()> {
@@ -1,26 +1,31 @@
-use crate::device::DEVICE;
-use crate::utils::BLOCK_SIZE;
-use alloc::boxed::Box;
-use alloc::collections::VecDeque;
+use crate::device::{Device, DeviceError};
+use crate::layout::Content;
 use alloc::sync::Arc;
-use log::info;
-use spin::{Once, RwLock};
+use alloc::vec;
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+use log::{error, info};
+use spin::RwLock;
 
 /// 需要使用读写锁保护数据，防止多个线程同时访问
 pub struct BlockCache {
     id: usize,
+    /// 缓存所属的块设备
+    device: Device,
     inner: RwLock<BlockCacheInner>,
 }
 
 struct BlockCacheInner {
     dirty: bool,
-    data: [u8; BLOCK_SIZE],
+    /// 长度为卷的扇区大小
+    data: Vec<u8>,
 }
 
 impl BlockCache {
-    pub fn new(block_id: usize, data: [u8; BLOCK_SIZE]) -> Self {
+    pub fn new(block_id: usize, device: Device, data: Vec<u8>) -> Self {
         Self {
             id: block_id,
+            device,
             inner: RwLock::new(BlockCacheInner { dirty: false, data }),
         }
     }
@@ -35,108 +40,561 @@ impl BlockCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        assert!(offset + type_size <= BLOCK_SIZE);
+        assert!(offset + type_size <= self.inner.read().data.len());
         let addr = self.addr_of_offset(offset);
         unsafe { &*(addr as *const T) }
     }
-    pub fn get_mut<T>(&self, offset: usize) -> &mut T
-    where
-        T: Sized,
-    {
+
+    pub fn read<T, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> V {
+        f(self.get_ref(offset))
+    }
+
+    pub fn write<T, V>(&self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
         let type_size = core::mem::size_of::<T>();
-        assert!(offset + type_size <= BLOCK_SIZE);
+        assert!(offset + type_size <= self.inner.read().data.len());
         let addr = self.addr_of_offset(offset);
+        self.inner.write().dirty = true;
+        f(unsafe { &mut *(addr as *mut T) })
+    }
+
+    /// 读取整个扇区的内容
+    pub fn read_content<V>(&self, f: impl FnOnce(&Content) -> V) -> V {
+        let inner = self.inner.read();
+        f(Content::new(&inner.data))
+    }
+
+    /// 修改整个扇区的内容
+    pub fn write_content<V>(&self, f: impl FnOnce(&mut Content) -> V) -> V {
         let mut inner = self.inner.write();
         inner.dirty = true;
-        unsafe { &mut *(addr as *mut T) }
+        f(Content::new_mut(&mut inner.data))
     }
 
-    pub fn read<T, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> V {
-        f(self.get_ref(offset))
+    /// 把缓存内容复制到`buf`
+    fn copy_to(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.inner.read().data);
     }
 
-    pub fn write<T, V>(&self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
-        f(self.get_mut(offset))
+    /// 用已经写入磁盘的内容覆盖缓存，缓存与磁盘一致因此清除脏标记
+    fn overwrite(&self, buf: &[u8]) {
+        let mut inner = self.inner.write();
+        inner.data.copy_from_slice(buf);
+        inner.dirty = false;
     }
 
-    pub fn sync(&self) {
+    /// 写回失败时保留脏标记，下次同步时重试
+    /// 返回是否真正写回了数据
+    pub fn sync(&self) -> Result<bool, DeviceError> {
         let mut inner = self.inner.write();
         let data = ((*inner).data).as_ref();
         if inner.dirty {
             info!("sync block {}", self.id);
-            DEVICE.get().unwrap().lock().write(self.id, data).unwrap();
+            self.device.lock().write(self.id, data)?;
             inner.dirty = false;
+            return Ok(true);
         }
+        Ok(false)
     }
 }
 
 impl Drop for BlockCache {
     fn drop(&mut self) {
-        self.sync()
+        if let Err(err) = self.sync() {
+            error!("lost block {} when drop cache: {:?}", self.id, err);
+        }
     }
 }
 
-pub static mut CACHE_MANAGER: Once<Box<dyn Cache>> = Once::new();
+/// 获取缓存块时的错误
+#[derive(Debug)]
+pub enum CacheError {
+    Io(DeviceError),
+    /// 所有缓存块都正在被使用，没有可以换出的缓存
+    Full,
+}
 
-pub struct CacheManager {
-    cache: VecDeque<Arc<BlockCache>>,
-    size: usize,
+impl From<DeviceError> for CacheError {
+    fn from(err: DeviceError) -> Self {
+        CacheError::Io(err)
+    }
+}
+
+/// 扇区的种类，每一类使用独立的容量与换出策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheClass {
+    /// 引导扇区、fs_info与fat表
+    Fat,
+    /// 目录项
+    Dir,
+    /// 文件内容
+    Data,
+}
+
+impl CacheClass {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// 缓存满时选择换出扇区的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// 换出最久未访问的扇区
+    Lru,
+    /// 换出最早载入的扇区，命中不改变顺序，适合一次性的顺序读写
+    Fifo,
+}
+
+/// 一类扇区的缓存配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// 最多缓存的扇区数
+    pub capacity: usize,
+    pub policy: CachePolicy,
+}
+
+impl CacheConfig {
+    pub const fn new(capacity: usize, policy: CachePolicy) -> Self {
+        Self { capacity, policy }
+    }
+}
+
+/// 各类扇区的缓存配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheOptions {
+    pub fat: CacheConfig,
+    pub dir: CacheConfig,
+    pub data: CacheConfig,
+}
+
+impl CacheOptions {
+    /// 所有种类使用相同的容量与LRU策略
+    pub const fn with_capacity(capacity: usize) -> Self {
+        let config = CacheConfig::new(capacity, CachePolicy::Lru);
+        Self {
+            fat: config,
+            dir: config,
+            data: config,
+        }
+    }
+    fn get(&self, class: CacheClass) -> CacheConfig {
+        match class {
+            CacheClass::Fat => self.fat,
+            CacheClass::Dir => self.dir,
+            CacheClass::Data => self.data,
+        }
+    }
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            fat: CacheConfig::new(32, CachePolicy::Lru),
+            dir: CacheConfig::new(32, CachePolicy::Lru),
+            data: CacheConfig::new(64, CachePolicy::Lru),
+        }
+    }
+}
+
+/// 缓存的统计信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// 被换出的扇区数
+    pub evictions: u64,
+    /// 写回磁盘的脏扇区数
+    pub write_backs: u64,
+}
+
+impl CacheStats {
+    /// 命中率，没有访问时为0
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
 }
 
 pub trait Cache: Send + Sync {
-    fn get_cache_by_id(&mut self, id: usize) -> Arc<BlockCache>;
-    fn sync(&self);
+    fn get_cache_by_id(
+        &mut self,
+        id: usize,
+        class: CacheClass,
+    ) -> Result<Arc<BlockCache>, CacheError>;
+    fn sync(&mut self) -> Result<(), DeviceError>;
+    /// 绕过缓存从`start`开始连续读取多个扇区，已缓存的扇区以缓存内容为准
+    fn read_blocks(&self, start: usize, buf: &mut [u8]) -> Result<(), DeviceError>;
+    /// 绕过缓存从`start`开始连续写入多个扇区，并同步已缓存扇区的内容
+    fn write_blocks(&self, start: usize, buf: &[u8]) -> Result<(), DeviceError>;
+    /// 一类扇区的统计信息
+    fn stats(&self, class: CacheClass) -> CacheStats;
+    fn reset_stats(&mut self);
+}
+
+/// 链表中表示空的下标
+const NIL: usize = usize::MAX;
+
+struct Node {
+    cache: Arc<BlockCache>,
+    prev: usize,
+    next: usize,
+}
+
+/// 一类扇区的缓存，节点按换出顺序串成双向链表
+struct Partition {
+    config: CacheConfig,
+    nodes: Vec<Node>,
+    /// 最后换出的节点
+    head: usize,
+    /// 最先换出的节点
+    tail: usize,
+    stats: CacheStats,
+}
+
+impl Partition {
+    fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            nodes: Vec::with_capacity(config.capacity),
+            head: NIL,
+            tail: NIL,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = NIL;
+        self.nodes[i].next = self.head;
+        match self.head {
+            NIL => self.tail = i,
+            head => self.nodes[head].prev = i,
+        }
+        self.head = i;
+    }
+
+    /// 命中时按照策略调整顺序
+    fn touch(&mut self, i: usize) {
+        if self.config.policy == CachePolicy::Lru {
+            self.unlink(i);
+            self.push_front(i);
+        }
+    }
+
+    /// 从最先换出的一端找到没有被其他地方引用的节点
+    fn find_victim(&self) -> Option<usize> {
+        let mut i = self.tail;
+        while i != NIL {
+            if Arc::strong_count(&self.nodes[i].cache) == 1 {
+                return Some(i);
+            }
+            i = self.nodes[i].prev;
+        }
+        None
+    }
+}
+
+/// 按扇区种类分区的块缓存
+///
+/// 扇区号通过哈希表索引到所在分区的节点，查找与调整顺序都是O(1)的；
+/// 每个扇区只会出现在一个分区中，先载入它的种类决定它所在的分区
+pub struct CacheManager {
+    device: Device,
+    /// 每个缓存块的大小，等于卷的扇区大小
+    block_size: usize,
+    /// 扇区号到(分区, 节点下标)的索引
+    index: HashMap<usize, (usize, usize)>,
+    partitions: [Partition; 3],
 }
 
 impl CacheManager {
-    pub fn new(size: usize) -> Self {
+    pub fn new(device: Device, block_size: usize, options: CacheOptions) -> Self {
+        let partitions = [CacheClass::Fat, CacheClass::Dir, CacheClass::Data]
+            .map(|class| Partition::new(options.get(class)));
+        let capacity = partitions.iter().map(|p| p.config.capacity).sum();
         CacheManager {
-            cache: VecDeque::new(),
-            size,
+            device,
+            block_size,
+            index: HashMap::with_capacity(capacity),
+            partitions,
+        }
+    }
+
+    /// 在`start`开始的`count`个扇区中，对已经缓存的扇区调用f
+    fn for_each_cached(&self, start: usize, count: usize, mut f: impl FnMut(usize, &BlockCache)) {
+        for id in start..start + count {
+            if let Some(&(p, i)) = self.index.get(&id) {
+                f(id - start, &self.partitions[p].nodes[i].cache);
+            }
         }
     }
 }
 
 impl Cache for CacheManager {
-    fn get_cache_by_id(&mut self, id: usize) -> Arc<BlockCache> {
-        let ans = self.cache.iter().find(|&cache| cache.id == id);
-        match ans {
-            Some(cache) => cache.clone(),
-            None => {
-                if self.cache.len() == self.size {
-                    // 找到引用计数为1的cache，即没有被其他线程引用的cache
-                    let change = self
-                        .cache
-                        .iter()
-                        .enumerate()
-                        .find(|(_index, cache)| Arc::strong_count(cache) == 1);
-                    if change.is_none() {
-                        panic!("no cache can be replaced");
-                    }
-                    let (index, _cache) = change.unwrap();
-                    self.cache.remove(index);
+    fn get_cache_by_id(
+        &mut self,
+        id: usize,
+        class: CacheClass,
+    ) -> Result<Arc<BlockCache>, CacheError> {
+        if let Some(&(p, i)) = self.index.get(&id) {
+            self.partitions[class.index()].stats.hits += 1;
+            let partition = &mut self.partitions[p];
+            partition.touch(i);
+            return Ok(partition.nodes[i].cache.clone());
+        }
+        let p = class.index();
+        let partition = &mut self.partitions[p];
+        partition.stats.misses += 1;
+        let mut buffer = vec![0u8; self.block_size];
+        if partition.nodes.len() < partition.config.capacity {
+            self.device.lock().read(id, &mut buffer)?;
+            let cache = Arc::new(BlockCache::new(id, self.device.clone(), buffer));
+            partition.nodes.push(Node {
+                cache: cache.clone(),
+                prev: NIL,
+                next: NIL,
+            });
+            let i = partition.nodes.len() - 1;
+            partition.push_front(i);
+            self.index.insert(id, (p, i));
+            return Ok(cache);
+        }
+        // 所有缓存都被引用时返回错误，由调用者释放缓存后重试
+        let i = partition.find_victim().ok_or(CacheError::Full)?;
+        // 先写回再替换，写回失败时缓存仍然保留
+        if partition.nodes[i].cache.sync()? {
+            partition.stats.write_backs += 1;
+        }
+        self.device.lock().read(id, &mut buffer)?;
+        let cache = Arc::new(BlockCache::new(id, self.device.clone(), buffer));
+        let old = core::mem::replace(&mut partition.nodes[i].cache, cache.clone());
+        partition.stats.evictions += 1;
+        partition.unlink(i);
+        partition.push_front(i);
+        self.index.remove(&old.id);
+        self.index.insert(id, (p, i));
+        Ok(cache)
+    }
+    fn sync(&mut self) -> Result<(), DeviceError> {
+        for partition in self.partitions.iter_mut() {
+            for node in partition.nodes.iter() {
+                let refcount = Arc::strong_count(&node.cache);
+                info!("ref count: {}", refcount);
+                if node.cache.sync()? {
+                    partition.stats.write_backs += 1;
                 }
-                let mut buffer = [0u8; BLOCK_SIZE];
-                DEVICE.get().unwrap().lock().read(id, &mut buffer).unwrap();
-                let cache = Arc::new(BlockCache::new(id, buffer));
-                self.cache.push_back(cache.clone());
-                cache
             }
         }
+        self.device.lock().flush()
+    }
+    fn read_blocks(&self, start: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
+        self.device
+            .lock()
+            .read_blocks(start, self.block_size, buf)?;
+        // 缓存中可能有尚未写回的修改
+        let block_size = self.block_size;
+        self.for_each_cached(start, buf.len() / block_size, |i, cache| {
+            cache.copy_to(&mut buf[i * block_size..(i + 1) * block_size]);
+        });
+        Ok(())
     }
-    fn sync(&self) {
-        for cache in self.cache.iter() {
-            let refcount = Arc::strong_count(cache);
-            info!("ref count: {}", refcount);
-            cache.sync();
+    fn write_blocks(&self, start: usize, buf: &[u8]) -> Result<(), DeviceError> {
+        self.device
+            .lock()
+            .write_blocks(start, self.block_size, buf)?;
+        let block_size = self.block_size;
+        self.for_each_cached(start, buf.len() / block_size, |i, cache| {
+            cache.overwrite(&buf[i * block_size..(i + 1) * block_size]);
+        });
+        Ok(())
+    }
+    fn stats(&self, class: CacheClass) -> CacheStats {
+        self.partitions[class.index()].stats
+    }
+    fn reset_stats(&mut self) {
+        for partition in self.partitions.iter_mut() {
+            partition.stats = CacheStats::default();
         }
     }
 }
 
-pub fn get_block_cache_by_id(block_id: usize) -> Arc<BlockCache> {
-    unsafe { CACHE_MANAGER.get_mut().unwrap().get_cache_by_id(block_id) }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{wrap_device, BlockDevice};
+    use spin::Mutex;
+
+    /// 记录单块读写次数的内存块设备
+    #[derive(Clone)]
+    struct RamDevice {
+        data: Arc<Mutex<Vec<u8>>>,
+        calls: Arc<Mutex<usize>>,
+    }
+    impl BlockDevice for RamDevice {
+        fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            *self.calls.lock() += 1;
+            let data = self.data.lock();
+            let start = block * buf.len();
+            buf.copy_from_slice(&data[start..start + buf.len()]);
+            Ok(buf.len())
+        }
+        fn write(&self, block: usize, buf: &[u8]) -> Result<usize, ()> {
+            *self.calls.lock() += 1;
+            let mut data = self.data.lock();
+            let start = block * buf.len();
+            data[start..start + buf.len()].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    fn make_manager() -> (RamDevice, CacheManager) {
+        let device = RamDevice {
+            data: Arc::new(Mutex::new(vec![0; 512 * 16])),
+            calls: Arc::new(Mutex::new(0)),
+        };
+        let manager = CacheManager::new(
+            wrap_device(device.clone()),
+            512,
+            CacheOptions::with_capacity(4),
+        );
+        (device, manager)
+    }
+
+    #[test]
+    fn test_read_blocks_fallback() {
+        let (device, manager) = make_manager();
+        device.data.lock()[512 * 3..512 * 5].fill(7);
+        let mut buf = vec![0u8; 512 * 4];
+        manager.read_blocks(2, &mut buf).unwrap();
+        // 默认实现逐块读取
+        assert_eq!(*device.calls.lock(), 4);
+        assert!(buf[..512].iter().all(|&b| b == 0));
+        assert!(buf[512..512 * 3].iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn test_direct_io_coherence() {
+        let (device, mut manager) = make_manager();
+        // 缓存中未写回的修改对直接读可见
+        let cache = manager.get_cache_by_id(3, CacheClass::Data).unwrap();
+        cache.write_content(|content| content.write().fill(1));
+        let mut buf = vec![0u8; 512 * 4];
+        manager.read_blocks(2, &mut buf).unwrap();
+        assert!(buf[512..1024].iter().all(|&b| b == 1));
+        assert!(device.data.lock()[512 * 3..512 * 4].iter().all(|&b| b == 0));
+        // 直接写会更新已缓存的扇区，且不会被旧的脏数据覆盖
+        manager.write_blocks(2, &[2u8; 512 * 4]).unwrap();
+        cache.read_content(|content| assert!(content.read().iter().all(|&b| b == 2)));
+        manager.sync().unwrap();
+        assert!(device.data.lock()[512 * 2..512 * 6].iter().all(|&b| b == 2));
+    }
 
-pub fn sync() {
-    unsafe { CACHE_MANAGER.get_mut().unwrap().sync() }
+    #[test]
+    fn test_lru_eviction() {
+        let (device, mut manager) = make_manager();
+        for id in 0..4 {
+            manager.get_cache_by_id(id, CacheClass::Data).unwrap();
+        }
+        // 访问0之后，最久未使用的是1
+        manager.get_cache_by_id(0, CacheClass::Data).unwrap();
+        manager.get_cache_by_id(4, CacheClass::Data).unwrap();
+        assert!(manager.index.contains_key(&0));
+        assert!(!manager.index.contains_key(&1));
+        // 命中时不访问设备
+        let calls = *device.calls.lock();
+        for id in [0, 2, 3, 4] {
+            manager.get_cache_by_id(id, CacheClass::Data).unwrap();
+        }
+        assert_eq!(*device.calls.lock(), calls);
+    }
+
+    #[test]
+    fn test_cache_full() {
+        let (_device, mut manager) = make_manager();
+        let held: Vec<_> = (0..4)
+            .map(|id| manager.get_cache_by_id(id, CacheClass::Data).unwrap())
+            .collect();
+        assert!(matches!(
+            manager.get_cache_by_id(4, CacheClass::Data),
+            Err(CacheError::Full)
+        ));
+        // 跳过仍在使用的缓存，换出没有被引用的缓存
+        let _first = held.into_iter().next();
+        manager.get_cache_by_id(4, CacheClass::Data).unwrap();
+        assert!(manager.index.contains_key(&0));
+        assert!(!manager.index.contains_key(&1));
+    }
+
+    #[test]
+    fn test_stats() {
+        let (_device, mut manager) = make_manager();
+        for id in [0, 1, 0, 2, 3, 4] {
+            manager.get_cache_by_id(id, CacheClass::Data).unwrap();
+        }
+        let cache = manager.get_cache_by_id(0, CacheClass::Data).unwrap();
+        cache.write_content(|content| content.write()[0] = 1);
+        drop(cache);
+        manager.sync().unwrap();
+        let stats = manager.stats(CacheClass::Data);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 5);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.write_backs, 1);
+        assert_eq!(manager.stats(CacheClass::Fat), CacheStats::default());
+        manager.reset_stats();
+        assert_eq!(manager.stats(CacheClass::Data), CacheStats::default());
+    }
+
+    #[test]
+    fn test_partition() {
+        let (device, mut manager) = make_manager();
+        manager.get_cache_by_id(0, CacheClass::Fat).unwrap();
+        // 大量读取文件内容不会换出fat扇区
+        for id in 1..16 {
+            manager.get_cache_by_id(id, CacheClass::Data).unwrap();
+        }
+        let calls = *device.calls.lock();
+        manager.get_cache_by_id(0, CacheClass::Fat).unwrap();
+        assert_eq!(*device.calls.lock(), calls);
+        assert_eq!(manager.stats(CacheClass::Fat).hits, 1);
+        // 已经缓存的扇区以其它种类访问时直接命中
+        manager.get_cache_by_id(15, CacheClass::Dir).unwrap();
+        assert_eq!(*device.calls.lock(), calls);
+        assert_eq!(manager.stats(CacheClass::Dir).hits, 1);
+    }
+
+    #[test]
+    fn test_fifo() {
+        let device = RamDevice {
+            data: Arc::new(Mutex::new(vec![0; 512 * 16])),
+            calls: Arc::new(Mutex::new(0)),
+        };
+        let options = CacheOptions {
+            data: CacheConfig::new(4, CachePolicy::Fifo),
+            ..CacheOptions::with_capacity(4)
+        };
+        let mut manager = CacheManager::new(wrap_device(device), 512, options);
+        for id in [0, 1, 2, 3, 0, 4] {
+            manager.get_cache_by_id(id, CacheClass::Data).unwrap();
+        }
+        // 命中不改变顺序，最早载入的0被换出
+        assert!(!manager.index.contains_key(&0));
+        assert!(manager.index.contains_key(&1));
+    }
 }
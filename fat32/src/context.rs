@@ -0,0 +1,73 @@
+//! 文件系统实例的上下文
+//!
+//! 每个`Fat32`实例拥有自己的块设备与块缓存，`Dir`/`File`/`Fat`通过上下文访问它们，
+//! 因此可以同时挂载多个fat32卷
+use crate::cache::{
+    BlockCache, Cache, CacheClass, CacheError, CacheManager, CacheOptions, CacheStats,
+};
+use crate::device::{Device, DeviceError};
+use crate::time::{DateTime, TimeProvider};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::fmt::{Debug, Formatter};
+use spin::Mutex;
+
+pub struct FsContext {
+    cache: Mutex<Box<dyn Cache>>,
+    /// 目录项时间戳的来源
+    time: Arc<dyn TimeProvider>,
+}
+
+impl FsContext {
+    /// `block_size`为卷的扇区大小
+    pub fn new(
+        device: Device,
+        block_size: usize,
+        cache: CacheOptions,
+        time: Arc<dyn TimeProvider>,
+    ) -> Self {
+        Self {
+            cache: Mutex::new(Box::new(CacheManager::new(device, block_size, cache))),
+            time,
+        }
+    }
+    /// 当前时间
+    pub fn now(&self) -> DateTime {
+        self.time.now()
+    }
+    /// 从当前卷的缓存中获取扇区，`class`决定扇区载入哪一类缓存
+    pub fn get_block_cache_by_id(
+        &self,
+        block_id: usize,
+        class: CacheClass,
+    ) -> Result<Arc<BlockCache>, CacheError> {
+        self.cache.lock().get_cache_by_id(block_id, class)
+    }
+    /// 绕过缓存连续读取多个扇区
+    pub fn read_blocks(&self, start: usize, buf: &mut [u8]) -> Result<(), DeviceError> {
+        self.cache.lock().read_blocks(start, buf)
+    }
+    /// 绕过缓存连续写入多个扇区
+    pub fn write_blocks(&self, start: usize, buf: &[u8]) -> Result<(), DeviceError> {
+        self.cache.lock().write_blocks(start, buf)
+    }
+    /// 一类扇区的缓存统计
+    pub fn cache_stats(&self, class: CacheClass) -> CacheStats {
+        self.cache.lock().stats(class)
+    }
+    pub fn reset_cache_stats(&self) {
+        self.cache.lock().reset_stats()
+    }
+    /// 将当前卷的缓存写回磁盘
+    pub fn sync(&self) -> Result<(), DeviceError> {
+        self.cache.lock().sync()
+    }
+}
+
+impl Debug for FsContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FsContext")
+            .field("time", &self.time)
+            .finish()
+    }
+}
@@ -1,5 +1,6 @@
+use alloc::boxed::Box;
 use alloc::sync::Arc;
-use spin::once::Once;
+use core::fmt::{Debug, Formatter};
 use spin::Mutex;
 
 /// the block device should be able to read and write blocks
@@ -8,6 +9,98 @@ pub trait BlockDevice: Send + Sync + 'static {
     fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, Self::Error>;
     fn write(&self, block: usize, buf: &[u8]) -> Result<usize, Self::Error>;
     fn flush(&self) -> Result<(), Self::Error>;
+    /// 从`block`开始连续读取多个块，`buf`的长度为`block_size`的整数倍
+    ///
+    /// 默认逐块调用[BlockDevice::read]，支持批量传输的设备应当覆盖该方法
+    fn read_blocks(
+        &self,
+        block: usize,
+        block_size: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let mut size = 0;
+        for (i, chunk) in buf.chunks_mut(block_size).enumerate() {
+            size += self.read(block + i, chunk)?;
+        }
+        Ok(size)
+    }
+    /// 从`block`开始连续写入多个块，`buf`的长度为`block_size`的整数倍
+    fn write_blocks(
+        &self,
+        block: usize,
+        block_size: usize,
+        buf: &[u8],
+    ) -> Result<usize, Self::Error> {
+        let mut size = 0;
+        for (i, chunk) in buf.chunks(block_size).enumerate() {
+            size += self.write(block + i, chunk)?;
+        }
+        Ok(size)
+    }
 }
 
-pub static DEVICE: Once<Arc<Mutex<dyn BlockDevice<Error = ()>>>> = Once::new();
+/// 块设备返回的错误
+///
+/// 不同设备的错误类型不同，这里将其擦除后在文件系统内部传递
+pub struct DeviceError(Box<dyn Debug + Send + Sync>);
+
+impl DeviceError {
+    pub fn new<E: Debug + Send + Sync + 'static>(err: E) -> Self {
+        Self(Box::new(err))
+    }
+}
+
+impl Debug for DeviceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// 文件系统实例独占的块设备
+pub type Device = Arc<Mutex<dyn BlockDevice<Error = DeviceError>>>;
+
+/// 将任意错误类型的块设备包装为[Device]
+struct DeviceWrapper<D>(D);
+
+impl<D: BlockDevice> BlockDevice for DeviceWrapper<D>
+where
+    D::Error: Debug + Send + Sync,
+{
+    type Error = DeviceError;
+    fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        self.0.read(block, buf).map_err(DeviceError::new)
+    }
+    fn write(&self, block: usize, buf: &[u8]) -> Result<usize, DeviceError> {
+        self.0.write(block, buf).map_err(DeviceError::new)
+    }
+    fn flush(&self) -> Result<(), DeviceError> {
+        self.0.flush().map_err(DeviceError::new)
+    }
+    fn read_blocks(
+        &self,
+        block: usize,
+        block_size: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, DeviceError> {
+        self.0
+            .read_blocks(block, block_size, buf)
+            .map_err(DeviceError::new)
+    }
+    fn write_blocks(
+        &self,
+        block: usize,
+        block_size: usize,
+        buf: &[u8],
+    ) -> Result<usize, DeviceError> {
+        self.0
+            .write_blocks(block, block_size, buf)
+            .map_err(DeviceError::new)
+    }
+}
+
+pub fn wrap_device<D: BlockDevice>(device: D) -> Device
+where
+    D::Error: Debug + Send + Sync,
+{
+    Arc::new(Mutex::new(DeviceWrapper(device)))
+}
@@ -2,15 +2,24 @@
 //!
 //! 文件的打开/创建/删除等操作都通过这树个形结构来完成,创建文件系统后处于根目录下
 //!
-use crate::cache::get_block_cache_by_id;
+use crate::cache::{CacheClass, CacheError};
+use crate::context::FsContext;
+use crate::device::DeviceError;
 use crate::entry::{EntryFlags, FullLoongEntry, LongEntry, ShortEntry};
-use crate::layout::{Bpb, Content, EntryBytes, Fat, FatEntry, MetaData, SectorData};
-use crate::utils::u32_from_le_bytes;
+use crate::iter::DirIter;
+use crate::layout::{Bpb, BpbError, Content, EntryBytes, Fat, FatEntry, MetaData};
+use crate::name::{
+    exact_short_name, generate_short_name, is_valid_long_name, ShortName, DOTDOT_NAME, DOT_NAME,
+    MAX_NAME_LEN,
+};
+use crate::time::DateTime;
+use crate::utils::{name_eq, u32_from_le_bytes};
 
-use alloc::collections::BTreeMap;
-use alloc::format;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::cmp::{max, min};
 use core::error::Error;
@@ -20,30 +29,39 @@ use fat32_trait::{DirectoryLike, FileLike};
 use log::{info, trace};
 use spin::RwLock;
 
+/// 目录项所在的扇区以及扇区内的偏移
+type EntryAddress = (usize, usize);
+
 #[derive(Debug, Clone)]
 pub struct Dir {
     /// 当前目录的起始簇号
     start_cluster: u32,
     /// 目录项位置(sector, offset)
     address: (usize, usize),
+    /// 8.3短文件名，根目录为空
+    short_name: String,
     /// 元数据信息
     meta: Arc<MetaData>,
     /// fat表
     fat: Arc<RwLock<Fat>>,
+    /// 所属文件系统的上下文
+    ctx: Arc<FsContext>,
     sub_dirs: Arc<RwLock<BTreeMap<String, Dir>>>,
     files: Arc<RwLock<BTreeMap<String, File>>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct File {
-    /// 文件的起始簇号
-    start_cluster: u32,
     /// 元数据
     meta: Arc<MetaData>,
     fat: Arc<RwLock<Fat>>,
+    /// 所属文件系统的上下文
+    ctx: Arc<FsContext>,
     /// 记录文件目录项的位置(sector, offset)
     /// offset是短目录项的位置
     address: (usize, usize),
+    /// 8.3短文件名
+    short_name: String,
 }
 
 impl Dir {
@@ -54,22 +72,26 @@ impl Dir {
         address: (usize, usize),
         meta: Arc<MetaData>,
         fat: Arc<RwLock<Fat>>,
-    ) -> Self {
-        let dir = Self::empty(start_cluster, address, meta, fat);
-        dir.load();
-        dir
+        ctx: Arc<FsContext>,
+    ) -> Result<Self, OperationError> {
+        let dir = Self::empty(start_cluster, address, meta, fat, ctx);
+        dir.load()?;
+        Ok(dir)
     }
     fn empty(
         start_cluster: u32,
         address: (usize, usize),
         meta: Arc<MetaData>,
         fat: Arc<RwLock<Fat>>,
+        ctx: Arc<FsContext>,
     ) -> Self {
         Dir {
             start_cluster,
             address,
+            short_name: String::new(),
             meta,
             fat,
+            ctx,
             sub_dirs: Arc::new(Default::default()),
             files: Arc::new(Default::default()),
         }
@@ -77,11 +99,14 @@ impl Dir {
     /// 从磁盘中读取目录信息
     /// 读取的目录信息包括子目录和文件
     /// 在读取时不能访问子目录和文件，以免出现错误
-    fn load(&self) {
+    fn load(&self) -> Result<(), OperationError> {
         // 当前目录包含的所有扇区号
-        let mut sectors = self.clusters_to_sectors();
-        let mut sub_dir = self.sub_dirs.write();
-        let mut files = self.files.write();
+        let mut sectors = self.clusters_to_sectors()?;
+        // 重新构建，避免留下已经被删除的项
+        let mut sub_dir = BTreeMap::new();
+        let mut files = BTreeMap::new();
+        // 长目录项可能跨越扇区以及簇
+        let mut full_long_entry = FullLoongEntry::new();
         'outer: loop {
             if sectors.is_empty() {
                 break;
@@ -89,28 +114,32 @@ impl Dir {
             let range = sectors.remove(0);
             let mut flag = false;
             for i in range {
-                let cache = get_block_cache_by_id(i);
-                cache.read(0, |content: &Content| {
-                    let mut full_long_entry = FullLoongEntry::new();
+                let cache = self.ctx.get_block_cache_by_id(i, CacheClass::Dir)?;
+                cache.read_content(|content: &Content| {
                     for (index, entry) in content.iter::<EntryBytes>().enumerate() {
                         //判断此项是否是合法的
                         info!("entry: {:x?}", entry);
                         if entry[0] == 0x00 {
                             flag = true;
                             return;
-                        } else if entry[0] == 0xE5 || entry[0] == 0x05 {
+                        } else if entry[0] == 0xE5 {
                             // 已经被弃用，但没有删除
+                            full_long_entry.clear();
                         } else {
                             // 根据第11位判断是长文件名还是短文件名
-                            let entry_flag = EntryFlags::from_bits(entry[11]).unwrap();
+                            let entry_flag = EntryFlags::from_bits_truncate(entry[11]);
                             info!("entry_flag:{:?}", entry_flag);
                             if entry_flag.contains(EntryFlags::LONG_NAME) {
                                 let long_entry = LongEntry::from_buffer(entry);
                                 full_long_entry.push(long_entry);
+                            } else if entry_flag.contains(EntryFlags::VOLUME_ID) {
+                                // 卷标不是文件
+                                full_long_entry.clear();
                             } else {
                                 let short_entry = ShortEntry::from_buffer(entry);
                                 // 此时到达一个新的短文件名,需要将之前的长文件名解析出来
-                                let mut name = full_long_entry.filename();
+                                let mut name =
+                                    full_long_entry.checked_filename(short_entry.check_sum());
                                 if name.is_empty() {
                                     name = short_entry.filename();
                                 } // .和..没有长目录项
@@ -118,23 +147,29 @@ impl Dir {
                                 full_long_entry.clear();
                                 // 判断是否是目录
                                 if entry_flag.contains(EntryFlags::DIRECTORY) {
-                                    let start_cluster = short_entry.start_cluster();
-                                    let dir = Dir::empty(
+                                    let mut start_cluster = short_entry.start_cluster();
+                                    if name == ".." && start_cluster == 0 {
+                                        // ..指向根目录时簇号记为0
+                                        start_cluster = self.meta.root_dir_cluster;
+                                    }
+                                    let mut dir = Dir::empty(
                                         start_cluster,
                                         (i, index * 32),
                                         self.meta.clone(),
                                         self.fat.clone(),
+                                        self.ctx.clone(),
                                     );
+                                    dir.short_name = short_entry.alias();
                                     sub_dir.insert(name, dir);
                                 } else {
-                                    let start_cluster = short_entry.start_cluster();
                                     info!("checksum: {}", short_entry.check_sum());
-                                    let file = File::new(
-                                        start_cluster,
+                                    let mut file = File::new(
                                         (i, index * 32),
                                         self.meta.clone(),
                                         self.fat.clone(),
+                                        self.ctx.clone(),
                                     );
+                                    file.short_name = short_entry.alias();
                                     files.insert(name, file);
                                 }
                             }
@@ -146,82 +181,123 @@ impl Dir {
                 }
             } // read one cluster over
         } // read all cluster over
+        *self.sub_dirs.write() = sub_dir;
+        *self.files.write() = files;
+        Ok(())
     }
-    /// 处理目录项名称
-    /// 1.当文件名小于8个字符时，不用关心
-    /// 2.当文件名大于8个字符，需要查找当前目录下是否有同名的文件，如果有，需要在文件名后面加上数字
-    fn name_to_short_name(&self, name: &str, dtype: DirEntryType) -> String {
-        if name == "." || name == ".." {
-            return name.to_string();
-        }
-        let mut short_name;
-        let mut name = name.to_string();
-        let mut ext = String::new();
-        if name.contains('.') {
-            let mut split = name.rsplitn(2, '.');
-            ext = split.next().unwrap().to_string();
-            name = split.next().unwrap().to_string();
-        };
-        if name.len() > 8 {
-            // 查找是否有同名的文件
-            let mut i = 1;
-            match dtype {
-                DirEntryType::Dir => {
-                    let sub_dirs = self.sub_dirs.read();
-                    sub_dirs.iter().for_each(|(key, _)| {
-                        if key.starts_with(&name) {
-                            i += 1;
+    /// 依次访问目录中所有未删除的目录项，直到0x00目录项或者f返回true
+    fn for_each_entry(
+        &self,
+        mut f: impl FnMut(EntryAddress, &EntryBytes) -> bool,
+    ) -> Result<(), OperationError> {
+        for range in self.clusters_to_sectors()? {
+            for sector in range {
+                let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+                let end = cache.read_content(|content: &Content| {
+                    for (index, entry) in content.iter::<EntryBytes>().enumerate() {
+                        if entry[0] == 0x00 {
+                            return true;
                         }
-                    });
-                }
-                DirEntryType::File => {
-                    let files = self.files.read();
-                    files.iter().for_each(|(key, _)| {
-                        if key.starts_with(&name) {
-                            i += 1;
+                        if entry[0] != 0xE5 && f((sector, index * 32), entry) {
+                            return true;
                         }
-                    });
+                    }
+                    false
+                });
+                if end {
+                    return Ok(());
                 }
-                _ => {}
             }
-            // 计算数字的长度
-            // 0-999999
-            let num = i.to_string();
-            let name = name.chars().take(8 - num.len() - 1).collect::<String>();
-            short_name = format!("{name}~{num}");
-        } else {
-            short_name = name;
         }
-        if !ext.is_empty() {
-            short_name = format!("{short_name}.{ext}");
+        Ok(())
+    }
+    /// 目录中所有短目录项的文件名
+    fn short_names(&self) -> Result<BTreeSet<ShortName>, OperationError> {
+        let mut names = BTreeSet::new();
+        self.for_each_entry(|_, entry| {
+            let attr = EntryFlags::from_bits_truncate(entry[11]);
+            if !attr.contains(EntryFlags::LONG_NAME) {
+                names.insert(ShortEntry::from_buffer(entry).short_name());
+            }
+            false
+        })?;
+        Ok(names)
+    }
+    /// 卷标目录项的位置以及卷标，只有根目录中才有
+    pub(crate) fn volume_label(&self) -> Result<Option<(EntryAddress, ShortName)>, OperationError> {
+        let mut ans = None;
+        self.for_each_entry(|address, entry| {
+            let attr = EntryFlags::from_bits_truncate(entry[11]);
+            if !attr.contains(EntryFlags::LONG_NAME) && attr.contains(EntryFlags::VOLUME_ID) {
+                ans = Some((address, ShortEntry::from_buffer(entry).short_name()));
+            }
+            ans.is_some()
+        })?;
+        Ok(ans)
+    }
+    /// 写入卷标目录项，label为None时删除卷标目录项
+    pub(crate) fn set_volume_label(&self, label: Option<&ShortName>) -> Result<(), OperationError> {
+        let old = self.volume_label()?;
+        match (old, label) {
+            (Some(((sector, offset), _)), Some(label)) => {
+                let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+                let now = self.ctx.now();
+                cache.write(offset, |entry: &mut EntryBytes| {
+                    let mut short_entry = ShortEntry::from_buffer(entry);
+                    short_entry.set_modify_time(now);
+                    *entry = short_entry.to_buffer();
+                    entry[0..11].copy_from_slice(label);
+                });
+            }
+            (Some(((sector, offset), _)), None) => {
+                let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+                cache.write(offset, |entry: &mut EntryBytes| entry[0] = 0xE5);
+            }
+            (None, Some(label)) => {
+                let short_entry =
+                    ShortEntry::from_short_name(label, EntryFlags::VOLUME_ID, 0, self.ctx.now());
+                let target_sectors = self.find_enough_entry(1)?;
+                self.write_entries(&short_entry, &FullLoongEntry::new(), &target_sectors)?;
+            }
+            (None, None) => {}
         }
-        if short_name.len() > 12 {
-            short_name = short_name.chars().take(12).collect::<String>();
+        Ok(())
+    }
+
+    /// 由长文件名生成目录中唯一的短文件名
+    /// reuse是即将被删除的目录项的短文件名，可以重新使用
+    fn name_to_short_name(
+        &self,
+        name: &str,
+        reuse: Option<&ShortName>,
+    ) -> Result<ShortName, OperationError> {
+        let mut names = self.short_names()?;
+        if let Some(reuse) = reuse {
+            names.remove(reuse);
         }
-        short_name
+        generate_short_name(name, |short| names.contains(short)).ok_or(OperationError::NoShortName)
     }
 
     fn make_entry(
         &self,
         name: &str,
-        short_name: &str,
+        short_name: &ShortName,
         start_cluster: u32,
         dtype: DirEntryType,
     ) -> Result<(ShortEntry, FullLoongEntry), OperationError> {
-        info!("name {name}'s short_name is {}", short_name);
+        info!("name {name}'s short_name is {:x?}", short_name);
         let attr = match dtype {
             DirEntryType::Dir | DirEntryType::Dot | DirEntryType::DotDot => EntryFlags::DIRECTORY,
             DirEntryType::File => EntryFlags::ARCHIVE,
         };
 
-        let short_entry = ShortEntry::new(short_name, attr, start_cluster);
+        let mut short_entry =
+            ShortEntry::from_short_name(short_name, attr, start_cluster, self.ctx.now());
         // 长目录项
         // 如果是.或者..目录项，不需要长目录项
         let full_long_entry = match dtype {
             DirEntryType::Dot | DirEntryType::DotDot => FullLoongEntry::new(),
-            DirEntryType::Dir | DirEntryType::File => {
-                FullLoongEntry::from_file_name(name, short_entry.check_sum())
-            }
+            DirEntryType::Dir | DirEntryType::File => long_entries(name, &mut short_entry),
         };
         info!("full_long_entry:{:#?}", full_long_entry);
         info!("short_entry:{:#?}", short_entry);
@@ -235,9 +311,9 @@ impl Dir {
         j: usize,
         need: usize,
         collect: &mut Vec<(usize, usize, usize)>,
-    ) {
-        let cache = get_block_cache_by_id(sector);
-        cache.read(0, |content: &Content| {
+    ) -> Result<(), OperationError> {
+        let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+        cache.read_content(|content: &Content| {
             let content = content.read();
             for i in 0..content.len() / 32 {
                 let entry_bytes = &content[i * 32..(i + 1) * 32];
@@ -263,30 +339,38 @@ impl Dir {
                 } // 找到足够的目录项,退出查找
             } // one sectors
         });
+        Ok(())
     }
 
     /// # 找到足够的位置存放目录项
-    /// 这些位置必须是连续的,由于长目录项最大为8个，段目录项为1个,
-    /// 9个目录项位置只会存在一个sector或者连续的两个sector中,
-    /// 但这两个sector可能会在不同的cluster中
+    /// 这些位置必须是连续的,由于长目录项最多为20个，短目录项为1个,
+    /// 这些目录项可能跨越多个sector，这些sector也可能会在不同的cluster中
+    /// 新分配的cluster中可能有旧数据，作为目录使用前需要先清零
+    fn zero_cluster(&self, cluster: u32) -> Result<(), OperationError> {
+        let start_sector = self.meta.cluster_to_sector(cluster);
+        let end_sector = start_sector + self.meta.sectors_per_cluster as usize;
+        for sector in start_sector..end_sector {
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+            cache.write_content(|content: &mut Content| content.write().fill(0));
+        }
+        Ok(())
+    }
+
     fn find_enough_entry(&self, need: usize) -> Result<Vec<(usize, usize)>, OperationError> {
         info!("find_enough_entry need:{}", need);
         let mut fat = self.fat.write();
         let cluster = self.start_cluster;
-        let mut cluster_chain = fat.get_cluster_chain(cluster); // 获取簇链
+        let mut cluster_chain = fat.get_cluster_chain(cluster)?; // 获取簇链
         trace!("cluster_chain:{:?}", cluster_chain);
-        let mut collect = Vec::new();
-        collect.reserve(need); // 预分配空间
-        let mut find_flag = false;
+        let mut collect = Vec::with_capacity(need); // 预分配空间
 
         trace!("begin to find entries");
         for (index, &cluster) in cluster_chain.iter().enumerate() {
             let start_sector = self.meta.cluster_to_sector(cluster);
             let end_sector = start_sector + self.meta.sectors_per_cluster as usize;
-            for (j, sector) in (start_sector..end_sector).into_iter().enumerate() {
-                self.find_enough_entry_inner(sector, index, j, need, &mut collect); //在一个sector中查找
+            for (j, sector) in (start_sector..end_sector).enumerate() {
+                self.find_enough_entry_inner(sector, index, j, need, &mut collect)?; //在一个sector中查找
                 if collect.len() == need {
-                    find_flag = true;
                     break;
                 }
             } // all sectors
@@ -294,29 +378,30 @@ impl Dir {
                 break;
             }
         } // all cluster
-          // 检查是否找到足够的目录项
-        if !find_flag {
+          // 没有找到足够的目录项，需要分配新的cluster
+          // 长文件名较长时一个cluster可能也不够
+        while collect.len() != need {
             trace!("not find enough entry, need allocate new cluster");
-            // 没有找到足够的目录项，需要分配新的cluster
-            let new_cluster = fat
-                .alloc_cluster()
-                .map_or(Err(OperationError::NoEnoughSpace), |cluster| Ok(cluster))?;
+            let new_cluster = fat.alloc_cluster()?;
             let cluster = cluster_chain.last().unwrap();
-            fat.set_entry(*cluster, FatEntry::Cluster(new_cluster), DirEntryType::Dir);
-            fat.set_entry(new_cluster, FatEntry::Eof, DirEntryType::Dir);
-            // 重新查找
-            // 此时保证了新分配的cluster一定是可以满足分配
-            // 但不需要重新开始分配
+            fat.set_entry(*cluster, FatEntry::Cluster(new_cluster), DirEntryType::Dir)?;
+            fat.set_entry(new_cluster, FatEntry::Eof, DirEntryType::Dir)?;
             cluster_chain.push(new_cluster);
-            let new_sector = self.meta.cluster_to_sector(new_cluster);
-            self.find_enough_entry_inner(
-                new_sector,
-                cluster_chain.len() - 1,
-                0,
-                need,
-                &mut collect,
-            );
-            assert_eq!(collect.len(), need);
+            self.zero_cluster(new_cluster)?;
+            let start_sector = self.meta.cluster_to_sector(new_cluster);
+            let end_sector = start_sector + self.meta.sectors_per_cluster as usize;
+            for (j, sector) in (start_sector..end_sector).enumerate() {
+                self.find_enough_entry_inner(
+                    sector,
+                    cluster_chain.len() - 1,
+                    j,
+                    need,
+                    &mut collect,
+                )?;
+                if collect.len() == need {
+                    break;
+                }
+            }
         }
         trace!("find entries success, len: {:?}", collect);
         // 找到足够的目录项，返回结果
@@ -339,24 +424,20 @@ impl Dir {
         target_sectors: &[(usize, usize)],
     ) -> Result<(usize, usize), OperationError> {
         // 将长目录项写入到磁盘中
-        // 倒序写入
+        // 长目录项已经是倒序的
         trace!("write long entries....");
-        full_long_entry
-            .iter()
-            .rev()
-            .enumerate()
-            .for_each(|(index, entry)| {
-                let (sector, offset) = target_sectors[index];
-                let cache = get_block_cache_by_id(sector);
-                cache.write(offset * 32, |content: &mut EntryBytes| {
-                    let entry = entry.to_buffer();
-                    content.copy_from_slice(&entry);
-                });
+        for (index, entry) in full_long_entry.iter().enumerate() {
+            let (sector, offset) = target_sectors[index];
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+            cache.write(offset * 32, |content: &mut EntryBytes| {
+                let entry = entry.to_buffer();
+                content.copy_from_slice(&entry);
             });
+        }
         // 将短目录项写入到磁盘中
         trace!("write short entry....");
         let (sector, offset) = target_sectors[full_long_entry.len()];
-        let cache = get_block_cache_by_id(sector);
+        let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
         cache.write(offset * 32, |content: &mut EntryBytes| {
             let entry = short_entry.to_buffer();
             content.copy_from_slice(&entry);
@@ -364,24 +445,25 @@ impl Dir {
         trace!("write entry success....");
         Ok((sector, offset * 32))
     }
-    fn clusters_to_sectors(&self) -> Vec<Range<usize>> {
+    fn clusters_to_sectors(&self) -> Result<Vec<Range<usize>>, OperationError> {
         // 获取文件夹占用的簇
-        let clusters = self.fat.read().get_cluster_chain(self.start_cluster);
+        let clusters = self.fat.read().get_cluster_chain(self.start_cluster)?;
         let mut ans = Vec::new();
         clusters.iter().for_each(|cluster| {
             let first_sector = self.meta.cluster_to_sector(*cluster);
             let end_sector = first_sector + self.meta.sectors_per_cluster as usize;
             ans.push(first_sector..end_sector);
         });
-        ans
+        Ok(ans)
     }
+    /// 写入目录项，返回短目录项的位置以及实际的短文件名
     fn add_dir_or_file(
         &self,
         name: &str,
-        short_name: &str,
+        short_name: &ShortName,
         cluster: u32,
         dtype: DirEntryType,
-    ) -> Result<(usize, usize), OperationError> {
+    ) -> Result<((usize, usize), String), OperationError> {
         info!(
             "add_dir_or_file name:{},cluster:{},dtype:{:?}",
             name, cluster, dtype
@@ -394,7 +476,32 @@ impl Dir {
         let target_sectors = self.find_enough_entry(need_entry_count)?;
         // 写入目录项
         let addr = self.write_entries(&short_entry, &full_long_entry, &target_sectors)?;
-        Ok(addr)
+        Ok((addr, short_entry.alias()))
+    }
+    /// 读取address处的短目录项
+    fn read_short_entry(&self, address: EntryAddress) -> Result<ShortEntry, OperationError> {
+        let cache = self.ctx.get_block_cache_by_id(address.0, CacheClass::Dir)?;
+        Ok(cache.read(address.1, |entry: &EntryBytes| {
+            ShortEntry::from_buffer(entry)
+        }))
+    }
+    /// 以新的名称写入source的副本，簇号、大小、属性以及时间都保持不变
+    /// source位于当前目录时可以重新使用它的短文件名
+    /// 返回短目录项的位置以及实际的短文件名
+    fn copy_entry(
+        &self,
+        name: &str,
+        source: &ShortEntry,
+        same_dir: bool,
+    ) -> Result<(EntryAddress, String), OperationError> {
+        let reuse = source.short_name();
+        let short_name = self.name_to_short_name(name, same_dir.then_some(&reuse))?;
+        let mut short_entry = source.clone();
+        short_entry.set_short_name(&short_name);
+        let full_long_entry = long_entries(name, &mut short_entry);
+        let target_sectors = self.find_enough_entry(full_long_entry.len() + 1)?;
+        let addr = self.write_entries(&short_entry, &full_long_entry, &target_sectors)?;
+        Ok((addr, short_entry.alias()))
     }
 
     /// 删除目录项
@@ -412,7 +519,7 @@ impl Dir {
         assert!(ans.is_some()); //
         let (index, _) = ans.unwrap();
         // 处理目录项跨扇区或者跨簇的情况
-        let cache = get_block_cache_by_id(address.0);
+        let cache = self.ctx.get_block_cache_by_id(address.0, CacheClass::Dir)?;
         trace!("delete short entry at {}, offset {}", address.0, address.1);
         let short_entry = cache.write(address.1, |entry: &mut EntryBytes| {
             let short_entry = ShortEntry::from_buffer(entry);
@@ -421,60 +528,36 @@ impl Dir {
         });
         assert_eq!(short_entry.start_cluster(), start_cluster);
         // 处理长目录项,需要逆向查找其位置
-        // 只要找到段目录项所在扇区以及其前一个扇区(可能位于前一个簇内)
-        let pre_sector = if address.0 != self.meta.cluster_to_sector(cluster_chain[index]) {
-            // 如果不是簇的第一个扇区，则前一个扇区就是当前扇区的前一个扇区
-            address.0 - 1
-        } else {
-            // 如果是簇的第一个扇区，则前一个扇区可能可能不存在
-            if index > 0 {
-                self.meta.cluster_to_sector(cluster_chain[index - 1])
-                    + self.meta.sectors_per_cluster as usize
-                    - 1
-            } else {
-                // 前一个扇区不存在的情况，说明目录项一定全部位于当前扇区
-                address.0
-            }
-        };
-        let mut entry_offset = address.1;
-        let mut entry_sector = address.0;
-        let mut flag = false;
-        loop {
-            let (sector, offset) = if entry_offset == 0 {
-                // 如果是扇区的第一个目录项，则需要查找前一个扇区的最后一个目录项
-                if pre_sector == entry_sector {
-                    assert_eq!(pre_sector, self.meta.root_dir_start_sector());
-                    trace!("stop find long entry");
-                    break;
-                } //
-                assert_ne!(pre_sector, entry_sector);
-                let t = (pre_sector, self.meta.bytes_per_sector as usize - 32);
-                entry_sector = pre_sector;
-                entry_offset = self.meta.bytes_per_sector as usize - 32;
-                t
-            } else {
-                let t = (entry_sector, entry_offset - 32);
-                entry_offset -= 32;
-                t
-            };
+        // 长目录项可能跨越多个扇区以及簇，从短目录项的位置依次向前查找
+        let entries_per_sector = self.meta.bytes_per_sector as usize / 32;
+        let slots = cluster_chain[..=index]
+            .iter()
+            .flat_map(|&cluster| {
+                let start = self.meta.cluster_to_sector(cluster);
+                start..start + self.meta.sectors_per_cluster as usize
+            })
+            .flat_map(|sector| (0..entries_per_sector).map(move |i| (sector, i * 32)))
+            .take_while(|&slot| slot != address)
+            .collect::<Vec<(usize, usize)>>();
+        for &(sector, offset) in slots.iter().rev() {
             trace!("find long entry in sector {}, offset {}", sector, offset);
-            let cache = get_block_cache_by_id(sector);
-            cache.write(offset, |entry_bytes: &mut EntryBytes| {
-                let entry_attr = EntryFlags::from_bits(entry_bytes[11]).unwrap();
-                trace!("entry attr: {:?}", entry_attr);
-                if entry_attr.contains(EntryFlags::LONG_NAME) {
-                    // 如果是长目录项，则将其标记为删除
-                    let entry = LongEntry::from_buffer(entry_bytes);
-                    assert_eq!(entry.check_sum(), short_entry.check_sum());
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+            let deleted = cache.write(offset, |entry_bytes: &mut EntryBytes| {
+                let entry_attr = EntryFlags::from_bits_truncate(entry_bytes[11]);
+                // 遇到短目录项、已删除的目录项或者属于其它文件的长目录项时停止
+                if entry_bytes[0] != 0xE5
+                    && entry_attr.contains(EntryFlags::LONG_NAME)
+                    && LongEntry::from_buffer(entry_bytes).check_sum() == short_entry.check_sum()
+                {
                     entry_bytes[0] = 0xE5;
                     trace!("delete long entry");
+                    true
                 } else {
-                    // 如果是段目录项，则直接返回
-                    flag = true;
-                    trace!("stop find long entry");
+                    false
                 }
             });
-            if flag {
+            if !deleted {
+                trace!("stop find long entry");
                 break;
             }
         }
@@ -483,60 +566,339 @@ impl Dir {
 
     /// 清空目录下的所有文件和目录
     fn clear(&self) -> Result<(), OperationError> {
-        if self.sub_dirs.read().is_empty() && self.files.read().is_empty() {
-            return Ok(());
-        }
+        // 目录可能还没有被读取过，需要先加载才能释放子目录和文件占用的簇
+        self.load()?;
         let file_names = self.files.read().keys().cloned().collect::<Vec<String>>();
-        file_names.iter().for_each(|file| {
-            self.delete_file(file).unwrap();
-        });
+        for file in file_names.iter() {
+            self.delete_file(file)?;
+        }
         let dir_names = self
             .sub_dirs
             .read()
             .keys()
             .cloned()
             .collect::<Vec<String>>();
-        dir_names.iter().for_each(|dir| {
-            self.delete_dir(dir).unwrap();
+        for dir in dir_names.iter() {
+            self.delete_dir(dir)?;
+        }
+        // 回收簇，保留第一个簇
+        self.fat
+            .write()
+            .truncate_chain(self.start_cluster, DirEntryType::Dir)?;
+        Ok(())
+    }
+
+    /// 与open相同，但返回具体的File，可以访问fat32特有的信息
+    pub fn open_file(&self, name: &str) -> Result<File, OperationError> {
+        self.find_file(name)
+            .map(|(_, file)| file)
+            .ok_or(OperationError::FileNotFound)
+    }
+
+    /// 查找子目录，名称不区分大小写，也可以使用8.3短文件名
+    /// 返回目录中保存的名称以及目录
+    pub(crate) fn find_dir(&self, name: &str) -> Option<(String, Dir)> {
+        let sub_dirs = self.sub_dirs.read();
+        find_dir_key(&sub_dirs, name).map(|key| {
+            let dir = sub_dirs[&key].clone();
+            (key, dir)
+        })
+    }
+
+    /// 查找文件，名称不区分大小写，也可以使用8.3短文件名
+    pub(crate) fn find_file(&self, name: &str) -> Option<(String, File)> {
+        let files = self.files.read();
+        find_file_key(&files, name).map(|key| {
+            let file = files[&key].clone();
+            (key, file)
+        })
+    }
+
+    /// 是否是根目录
+    pub(crate) fn is_root(&self) -> bool {
+        self.start_cluster == self.meta.root_dir_cluster
+    }
+
+    /// 重新读取目录内容
+    pub(crate) fn reload(&self) -> Result<(), OperationError> {
+        self.load()
+    }
+
+    /// 与cd相同，但返回具体的Dir
+    pub fn open_dir(&self, name: &str) -> Result<Dir, OperationError> {
+        let (_, dir) = self.find_dir(name).ok_or(OperationError::DirNotFound)?;
+        dir.load()?;
+        Ok(dir)
+    }
+
+    /// 按磁盘上的顺序遍历目录项
+    pub fn iter(&self) -> Result<DirIter, OperationError> {
+        self.iter_from(0)
+    }
+
+    /// 从offset处开始遍历目录项，offset来自`DirEntry::next_offset`
+    pub fn iter_from(&self, offset: u64) -> Result<DirIter, OperationError> {
+        DirIter::new(
+            self.start_cluster,
+            offset,
+            self.meta.clone(),
+            self.fat.clone(),
+            self.ctx.clone(),
+        )
+    }
+
+    /// 获取当前目录下文件或子目录的元数据
+    pub fn metadata(&self, name: &str) -> Result<Metadata, OperationError> {
+        if let Some((_, file)) = self.find_file(name) {
+            return file.metadata();
+        }
+        let (_, dir) = self.find_dir(name).ok_or(OperationError::NotFound)?;
+        dir.entry_metadata()
+    }
+
+    /// 根据目录自身的目录项生成元数据
+    fn entry_metadata(&self) -> Result<Metadata, OperationError> {
+        let cluster_count = self.fat.read().chain_len(self.start_cluster)?;
+        let bytes_per_cluster = self.meta.bytes_per_cluster();
+        // 根目录没有目录项
+        if self.address == (0, 0) {
+            let allocated_size = cluster_count as u64 * bytes_per_cluster as u64;
+            return Ok(Metadata {
+                attr: EntryFlags::DIRECTORY,
+                created: DateTime::EPOCH,
+                modified: DateTime::EPOCH,
+                accessed: DateTime::EPOCH,
+                size: allocated_size,
+                allocated_size,
+                start_cluster: self.start_cluster,
+                cluster_count,
+                short_name: String::new(),
+            });
+        }
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        let short_entry = cache.read(self.address.1, |entry: &EntryBytes| {
+            ShortEntry::from_buffer(entry)
+        });
+        Ok(Metadata::new(
+            &short_entry,
+            cluster_count,
+            bytes_per_cluster,
+        ))
+    }
+
+    /// 目录中除了.和..之外没有其它目录项
+    fn is_empty(&self) -> Result<bool, OperationError> {
+        for entry in self.iter()? {
+            let entry = entry?;
+            if entry.name != "." && entry.name != ".." {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 将..目录项指向parent，parent是根目录时簇号记为0
+    fn set_parent(&self, parent: &Dir) -> Result<(), OperationError> {
+        let cluster = if parent.is_root() {
+            0
+        } else {
+            parent.start_cluster
+        };
+        let sector = self.meta.cluster_to_sector(self.start_cluster);
+        let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+        cache.write(32, |entry: &mut EntryBytes| {
+            if entry[0..11] == DOTDOT_NAME {
+                let mut short_entry = ShortEntry::from_buffer(entry);
+                short_entry.set_start_cluster(cluster);
+                *entry = short_entry.to_buffer();
+            }
         });
-        // 回收簇
-        let fat = self.fat.write();
-        let cluster_chain = fat.get_cluster_chain(self.start_cluster);
-        trace!("clear dir, cluster_chain: {:?}", cluster_chain);
-        for &i in cluster_chain.iter().skip(1) {
-            fat.set_entry(i, FatEntry::Free, DirEntryType::File);
-        } // 跳过了第一个簇
-          // 将第一个簇指向结束标志
-        fat.set_entry(self.start_cluster, FatEntry::Eof, DirEntryType::File);
+        self.sub_dirs
+            .write()
+            .insert("..".to_string(), parent.clone());
+        Ok(())
+    }
+
+    /// 将当前目录中的old_name移动到dst目录中并重命名为new_name
+    /// 只移动目录项，起始簇号以及文件内容都不变，移动目录时会更新其中的..目录项。
+    /// 目标已经存在时会被替换，文件只能替换文件，目录只能替换空目录，
+    /// 替换时先写入新的目录项，再删除目标以及原来的目录项
+    pub fn rename(&self, old_name: &str, dst: &Dir, new_name: &str) -> Result<(), OperationError> {
+        check_long_name(new_name)?;
+        if new_name == "." || new_name == ".." {
+            return Err(OperationError::InvalidDirName);
+        }
+        let same_dir = self.start_cluster == dst.start_cluster;
+        if let Some((key, mut file)) = self.find_file(old_name) {
+            if dst.find_dir(new_name).is_some() {
+                return Err(OperationError::DirExist);
+            }
+            let target = dst.find_file(new_name).map(|(target, _)| target);
+            if same_dir && target.as_ref() == Some(&key) {
+                // 同一个目录中只改变大小写
+                return self.rename_file(&key, new_name);
+            }
+            let source = self.read_short_entry(file.address)?;
+            let (address, short_name) = dst.copy_entry(new_name, &source, same_dir)?;
+            if let Some(target) = target {
+                dst.delete_file(&target)?;
+            }
+            self.files.write().remove(&key);
+            let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster)?;
+            self.delete_entry(file.start_cluster()?, file.address, &cluster_chain)?;
+            file.address = address;
+            file.short_name = short_name;
+            dst.files.write().insert(new_name.to_string(), file);
+            return Ok(());
+        }
+        let (key, dir) = self.find_dir(old_name).ok_or(OperationError::NotFound)?;
+        if key == "." || key == ".." {
+            return Err(OperationError::InvalidDirName);
+        }
+        if dst.find_file(new_name).is_some() {
+            return Err(OperationError::FileExist);
+        }
+        let target = dst.find_dir(new_name);
+        if same_dir && matches!(&target, Some((target, _)) if *target == key) {
+            return self.rename_dir(&key, new_name);
+        }
+        // 不能移动到自己或者自己的子目录中
+        let mut ancestor = dst.clone();
+        loop {
+            if ancestor.start_cluster == dir.start_cluster {
+                return Err(OperationError::InvalidMove);
+            }
+            if ancestor.is_root() {
+                break;
+            }
+            ancestor = ancestor.parent()?;
+        }
+        if let Some((_, target)) = &target {
+            if !target.is_empty()? {
+                return Err(OperationError::DirNotEmpty);
+            }
+        }
+        let source = self.read_short_entry(dir.address)?;
+        let (address, short_name) = dst.copy_entry(new_name, &source, same_dir)?;
+        if let Some((target, _)) = target {
+            dst.delete_dir(&target)?;
+        }
+        self.sub_dirs.write().remove(&key);
+        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster)?;
+        self.delete_entry(dir.start_cluster, dir.address, &cluster_chain)?;
+        if !same_dir {
+            dir.set_parent(dst)?;
+        }
+        let dir = Dir {
+            address,
+            short_name,
+            ..dir
+        };
+        dst.sub_dirs.write().insert(new_name.to_string(), dir);
         Ok(())
     }
 }
 
+/// 查找名称在map中保存的形式
+/// 依次尝试精确匹配、忽略大小写匹配以及8.3短文件名匹配
+fn find_key<V>(
+    map: &BTreeMap<String, V>,
+    name: &str,
+    short_name: impl Fn(&V) -> &str,
+) -> Option<String> {
+    if map.contains_key(name) {
+        return Some(name.to_string());
+    }
+    map.iter()
+        .find(|(key, _)| name_eq(key, name))
+        .or_else(|| {
+            // .和..保存的是目录本身以及上一级目录，不参与短文件名匹配
+            map.iter().find(|(key, v)| {
+                key.as_str() != "." && key.as_str() != ".." && name_eq(short_name(v), name)
+            })
+        })
+        .map(|(key, _)| key.clone())
+}
+
+/// 名称可以直接用短文件名表示时只设置大小写标志，否则生成长目录项
+fn long_entries(name: &str, short_entry: &mut ShortEntry) -> FullLoongEntry {
+    match exact_short_name(name) {
+        Some((exact, case)) if exact == short_entry.short_name() => {
+            short_entry.set_case(case);
+            FullLoongEntry::new()
+        }
+        _ => {
+            short_entry.set_case(0);
+            FullLoongEntry::from_file_name(name, short_entry.check_sum())
+        }
+    }
+}
+
+/// 检查新建或重命名时使用的名称
+fn check_long_name(name: &str) -> Result<(), OperationError> {
+    if name.encode_utf16().count() > MAX_NAME_LEN {
+        return Err(OperationError::NameTooLong);
+    }
+    if !is_valid_long_name(name) {
+        return Err(OperationError::InvalidName);
+    }
+    Ok(())
+}
+
+fn find_dir_key(sub_dirs: &BTreeMap<String, Dir>, name: &str) -> Option<String> {
+    find_key(sub_dirs, name, |dir| &dir.short_name)
+}
+
+fn find_file_key(files: &BTreeMap<String, File>, name: &str) -> Option<String> {
+    find_key(files, name, |file| &file.short_name)
+}
+
 impl DirectoryLike for Dir {
     type Error = OperationError;
     fn create_dir(&self, name: &str) -> Result<(), OperationError> {
-        let short_name = self.name_to_short_name(name, DirEntryType::Dir);
+        check_long_name(name)?;
         // 创建文件夹时，防止其它线程读取
         let mut sub_dirs = self.sub_dirs.write();
-        // 检查是否已经存在同名的文件夹
-        if sub_dirs.contains_key(name) {
+        // 检查是否已经存在同名的文件夹，不区分大小写
+        if find_dir_key(&sub_dirs, name).is_some() {
             return Err(OperationError::DirExist);
         }
-        let cluster = self
-            .fat
-            .write()
-            .alloc_cluster()
-            .map_or(Err(OperationError::NoEnoughSpace), |cluster| Ok(cluster))?; // 分配簇
+        let short_name = self.name_to_short_name(name, None)?;
+        let cluster = self.fat.write().alloc_cluster()?; // 分配簇
         self.fat
             .write()
-            .set_entry(cluster, FatEntry::Eof, DirEntryType::Dir); //写入fat表
+            .set_entry(cluster, FatEntry::Eof, DirEntryType::Dir)?; //写入fat表
         info!("create dir {name} at {cluster} cluster");
-        let address = self.add_dir_or_file(name, &short_name, cluster, DirEntryType::Dir)?;
+        self.zero_cluster(cluster)?;
+        let (address, short_name) =
+            match self.add_dir_or_file(name, &short_name, cluster, DirEntryType::Dir) {
+                Ok(ans) => ans,
+                Err(err) => {
+                    // 目录项写入失败时释放分配的簇
+                    self.fat.write().free_chain(cluster)?;
+                    return Err(err);
+                }
+            };
         // 创建目录
-        let dir = Dir::empty(cluster, address, self.meta.clone(), self.fat.clone());
+        let mut dir = Dir::empty(
+            cluster,
+            address,
+            self.meta.clone(),
+            self.fat.clone(),
+            self.ctx.clone(),
+        );
+        dir.short_name = short_name;
         // 创建目录的.和..目录项
-        dir.add_dir_or_file(".", ".", cluster, DirEntryType::Dot)?;
-        dir.add_dir_or_file("..", "..", self.start_cluster, DirEntryType::DotDot)?;
+        dir.add_dir_or_file(".", &DOT_NAME, cluster, DirEntryType::Dot)?;
+        // 父目录是根目录时..的簇号为0
+        let parent_cluster = if self.is_root() {
+            0
+        } else {
+            self.start_cluster
+        };
+        dir.add_dir_or_file("..", &DOTDOT_NAME, parent_cluster, DirEntryType::DotDot)?;
         dir.sub_dirs.write().insert(".".to_string(), dir.clone());
         dir.sub_dirs.write().insert("..".to_string(), self.clone());
         sub_dirs.insert(name.to_string(), dir);
@@ -544,22 +906,32 @@ impl DirectoryLike for Dir {
     }
 
     fn create_file(&self, name: &str) -> Result<(), OperationError> {
-        let short_name = self.name_to_short_name(name, DirEntryType::File);
+        check_long_name(name)?;
         let mut sub_files = self.files.write();
-        // 检查是否已经存在同名的文件
-        if sub_files.contains_key(name) {
+        // 检查是否已经存在同名的文件，不区分大小写
+        if find_file_key(&sub_files, name).is_some() {
             return Err(OperationError::FileExist);
         }
-        let cluster = self
-            .fat
-            .write()
-            .alloc_cluster()
-            .map_or(Err(OperationError::NoEnoughSpace), |cluster| Ok(cluster))?; // 分配簇
+        let short_name = self.name_to_short_name(name, None)?;
+        let cluster = self.fat.write().alloc_cluster()?; // 分配簇
         self.fat
             .write()
-            .set_entry(cluster, FatEntry::Eof, DirEntryType::Dir); //写入fat表
-        let address = self.add_dir_or_file(name, &short_name, cluster, DirEntryType::File)?; //写入目录项
-        let file = File::new(cluster, address, self.meta.clone(), self.fat.clone());
+            .set_entry(cluster, FatEntry::Eof, DirEntryType::Dir)?; //写入fat表
+        let (address, short_name) =
+            match self.add_dir_or_file(name, &short_name, cluster, DirEntryType::File) {
+                Ok(ans) => ans,
+                Err(err) => {
+                    self.fat.write().free_chain(cluster)?;
+                    return Err(err);
+                }
+            }; //写入目录项
+        let mut file = File::new(
+            address,
+            self.meta.clone(),
+            self.fat.clone(),
+            self.ctx.clone(),
+        );
+        file.short_name = short_name;
         sub_files.insert(name.to_string(), file); //添加到文件列表
         Ok(())
     }
@@ -572,17 +944,20 @@ impl DirectoryLike for Dir {
             return Ok(());
         }
         let mut sub_dirs = self.sub_dirs.write();
-        let dir = sub_dirs.remove(name).ok_or(OperationError::DirNotFound)?;
-        dir.clear()?;
+        let key = find_dir_key(&sub_dirs, name).ok_or(OperationError::DirNotFound)?;
+        if key == "." || key == ".." {
+            return Ok(());
+        }
+        let dir = sub_dirs.remove(&key).ok_or(OperationError::DirNotFound)?;
         // 递归删除子文件夹
-        let start_cluster = dir.start_cluster;
-        // 删除分配的簇
-        self.fat
-            .write()
-            .set_entry(start_cluster, FatEntry::Free, DirEntryType::Dir);
+        dir.clear()?;
+        // 删除分配的簇，簇号为0时没有分配簇
+        if dir.start_cluster != 0 {
+            self.fat.write().free_chain(dir.start_cluster)?;
+        }
         // 删除目录项
         info!("begin to delete dir entry...");
-        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster);
+        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster)?;
         self.delete_entry(dir.start_cluster, dir.address, &cluster_chain)?;
         info!("delete dir entry success");
         Ok(())
@@ -595,18 +970,20 @@ impl DirectoryLike for Dir {
         trace!("delete file {}", name);
         let mut sub_file = self.files.write();
         // 检查是否存在此文件
-        let file = sub_file.remove(name).ok_or(OperationError::FileNotFound)?;
-        // 清空文件内容
-        trace!("clear file content");
-        file.clear();
+        let key = find_file_key(&sub_file, name).ok_or(OperationError::FileNotFound)?;
+        let file = sub_file.remove(&key).ok_or(OperationError::FileNotFound)?;
         // 释放簇
         trace!("free cluster");
-        let fat = self.fat.write();
-        fat.set_entry(file.start_cluster, FatEntry::Free, DirEntryType::File);
+        let mut fat = self.fat.write();
+        // 其它系统创建的空文件簇号为0，没有分配簇
+        let start_cluster = file.start_cluster()?;
+        if start_cluster != 0 {
+            fat.free_chain(start_cluster)?;
+        }
         // 删除目录项
         // File 包含了文件的短目录项位置,需要找到长目录项的位置
-        let cluster_chain = fat.get_cluster_chain(self.start_cluster); //获取目录的簇链
-        self.delete_entry(file.start_cluster, file.address, &cluster_chain)?; //删除目录项
+        let cluster_chain = fat.get_cluster_chain(self.start_cluster)?; //获取目录的簇链
+        self.delete_entry(start_cluster, file.address, &cluster_chain)?; //删除目录项
         Ok(())
     }
 
@@ -616,24 +993,11 @@ impl DirectoryLike for Dir {
         &self,
         path: &str,
     ) -> Result<Arc<dyn DirectoryLike<Error = Self::Error>>, OperationError> {
-        let dir = self
-            .sub_dirs
-            .read()
-            .get(path)
-            .map_or(Err(OperationError::DirNotFound), |dir| {
-                Ok(Arc::new(dir.clone()))
-            })?;
-        dir.load();
-        Ok(dir)
+        Ok(Arc::new(self.open_dir(path)?))
     }
 
     fn open(&self, name: &str) -> Result<Arc<dyn FileLike<Error = Self::Error>>, OperationError> {
-        self.files
-            .read()
-            .get(name)
-            .map_or(Err(OperationError::FileNotFound), |file| {
-                Ok(Arc::new(file.clone()))
-            })
+        Ok(Arc::new(self.open_file(name)?))
     }
 
     /// 返回当前目录下的文件与子目录
@@ -648,112 +1012,265 @@ impl DirectoryLike for Dir {
         Ok(ans)
     }
     /// 重命名某个文件
+    /// 只改变大小写时也会重新生成目录项，大小、属性以及时间保持不变
+    /// 先写入新的目录项再删除原来的目录项，空间不足时原来的目录项不受影响
     fn rename_file(&self, old_name: &str, new_name: &str) -> Result<(), Self::Error> {
-        let file = self
-            .files
-            .write()
-            .remove(old_name)
-            .ok_or(OperationError::FileNotFound)?;
-        let short_name = self.name_to_short_name(new_name, DirEntryType::File);
+        check_long_name(new_name)?;
         let mut files = self.files.write();
+        let key = find_file_key(&files, old_name).ok_or(OperationError::FileNotFound)?;
+        // 新名称不能与其它文件冲突，只改变大小写时除外
+        if let Some(other) = find_file_key(&files, new_name) {
+            if other != key {
+                return Err(OperationError::FileExist);
+            }
+        }
+        let file = &files[&key];
+        // 生成新的目录项
+        let source = self.read_short_entry(file.address)?;
+        let (address, short_name) = self.copy_entry(new_name, &source, true)?;
         // 删除原来的目录项
-        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster); //获取目录的簇链
-        self.delete_entry(file.start_cluster, file.address, &cluster_chain)?; //删除目录项
-                                                                              // 生成新的目录项
-        self.add_dir_or_file(
-            new_name,
-            &short_name,
-            file.start_cluster,
-            DirEntryType::File,
-        )?;
+        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster)?; //获取目录的簇链
+        self.delete_entry(file.start_cluster()?, file.address, &cluster_chain)?; //删除目录项
+        let mut file = files.remove(&key).ok_or(OperationError::FileNotFound)?;
+        file.address = address;
+        file.short_name = short_name;
         files.insert(new_name.to_string(), file);
         Ok(())
     }
     /// 重命名某个目录
     fn rename_dir(&self, old_name: &str, new_name: &str) -> Result<(), Self::Error> {
-        let dir = self
-            .sub_dirs
-            .write()
-            .remove(old_name)
-            .ok_or(OperationError::DirNotFound)?;
-        let short_name = self.name_to_short_name(new_name, DirEntryType::Dir);
+        check_long_name(new_name)?;
+        let mut sub_dirs = self.sub_dirs.write();
+        let key = find_dir_key(&sub_dirs, old_name).ok_or(OperationError::DirNotFound)?;
+        if key == "." || key == ".." {
+            return Err(OperationError::InvalidDirName);
+        }
+        if let Some(other) = find_dir_key(&sub_dirs, new_name) {
+            if other != key {
+                return Err(OperationError::DirExist);
+            }
+        }
+        let dir = &sub_dirs[&key];
+        // 生成新的目录项
+        let source = self.read_short_entry(dir.address)?;
+        let (address, short_name) = self.copy_entry(new_name, &source, true)?;
         // 删除原来的目录项
-        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster); //获取目录的簇链
+        let cluster_chain = self.fat.read().get_cluster_chain(self.start_cluster)?; //获取目录的簇链
         self.delete_entry(dir.start_cluster, dir.address, &cluster_chain)?; //删除目录项
-                                                                            // 生成新的目录项
-        self.add_dir_or_file(new_name, &short_name, dir.start_cluster, DirEntryType::Dir)?;
-        self.sub_dirs.write().insert(new_name.to_string(), dir);
+        let dir = sub_dirs.remove(&key).ok_or(OperationError::DirNotFound)?;
+        let dir = Dir {
+            address,
+            short_name,
+            ..dir
+        };
+        sub_dirs.insert(new_name.to_string(), dir);
         Ok(())
     }
 }
 
 impl File {
     pub fn new(
-        start_cluster: u32,
         address: (usize, usize),
         meta: Arc<MetaData>,
         fat: Arc<RwLock<Fat>>,
+        ctx: Arc<FsContext>,
     ) -> Self {
         Self {
-            start_cluster,
             meta,
             fat,
+            ctx,
             address,
+            short_name: String::new(),
         }
     }
     #[allow(unused)]
-    fn empty() -> Self {
+    fn empty(ctx: Arc<FsContext>) -> Self {
         Self {
-            start_cluster: 0,
             meta: Arc::new(Default::default()),
-            fat: Arc::new(RwLock::new(Fat::empty())),
+            fat: Arc::new(RwLock::new(Fat::empty(ctx.clone()))),
+            ctx,
             address: (0, 0),
+            short_name: String::new(),
         }
     }
-    /// 获取文件占用的簇
-    /// cluster:[sector]-[sector]-[sector]-[sector]
-    ///  |
-    /// cluster
-    /// |
-    /// cluster
-    fn calculate_sectors_without_alloc(
+    /// 依次访问[offset, offset+size)范围覆盖的扇区
+    /// f的参数为(扇区号, 扇区内偏移, 长度)
+    /// 直接沿fat表遍历簇链，不分配内存
+    fn for_each_sector<F>(
         &self,
+        fat: &Fat,
         offset: u32,
         size: u32,
-        cluster_chain: &[u32],
-    ) -> Vec<usize> {
-        // 计算簇内偏移量
-        let cluster_offset = offset % self.meta.bytes_per_cluster();
-        // 计算开始簇号
-        let start_cluster_index = offset / self.meta.bytes_per_cluster();
-        // 计算簇内的开始扇区号
-        let mut start_sector_index = cluster_offset / self.meta.bytes_per_sector as u32;
-        let mut ans = Vec::new();
-        let mut size = size;
-        for cluster in cluster_chain.iter().skip(start_cluster_index as usize) {
-            let start_sector = self.meta.cluster_to_sector(*cluster) + start_sector_index as usize;
-            let end_sector =
-                self.meta.cluster_to_sector(*cluster) + self.meta.sectors_per_cluster as usize;
-            for sector in start_sector..end_sector {
-                ans.push(sector);
-                size = size.saturating_sub(self.meta.bytes_per_sector as u32);
-                if size == 0 {
-                    return ans;
+        mut f: F,
+    ) -> Result<(), OperationError>
+    where
+        F: FnMut(usize, usize, usize) -> Result<(), OperationError>,
+    {
+        if size == 0 {
+            return Ok(());
+        }
+        let bytes_per_cluster = self.meta.bytes_per_cluster() as usize;
+        let bytes_per_sector = self.meta.bytes_per_sector as usize;
+        // 跳过offset之前的簇
+        let mut cluster = self.start_cluster()?;
+        for _ in 0..offset as usize / bytes_per_cluster {
+            match fat.get_entry(cluster)? {
+                FatEntry::Cluster(next) => cluster = next,
+                _ => return Ok(()),
+            }
+        }
+        // 簇内偏移量
+        let mut pos = offset as usize % bytes_per_cluster;
+        let mut size = size as usize;
+        loop {
+            let sector = self.meta.cluster_to_sector(cluster) + pos / bytes_per_sector;
+            let start = pos % bytes_per_sector;
+            let len = min(bytes_per_sector - start, size);
+            f(sector, start, len)?;
+            size -= len;
+            if size == 0 {
+                return Ok(());
+            }
+            pos += len;
+            if pos == bytes_per_cluster {
+                pos = 0;
+                match fat.get_entry(cluster)? {
+                    FatEntry::Cluster(next) => cluster = next,
+                    _ => return Ok(()),
+                }
+            }
+        }
+    }
+    /// 与[File::for_each_sector]类似，但把物理上连续的簇合并为一段
+    /// f的参数为(起始扇区号, 扇区内偏移, 长度)，长度可以跨越多个扇区
+    fn for_each_extent<F>(
+        &self,
+        fat: &Fat,
+        offset: u32,
+        size: u32,
+        mut f: F,
+    ) -> Result<(), OperationError>
+    where
+        F: FnMut(usize, usize, usize) -> Result<(), OperationError>,
+    {
+        if size == 0 {
+            return Ok(());
+        }
+        let bytes_per_cluster = self.meta.bytes_per_cluster() as usize;
+        let bytes_per_sector = self.meta.bytes_per_sector as usize;
+        let mut cluster = self.start_cluster()?;
+        for _ in 0..offset as usize / bytes_per_cluster {
+            match fat.get_entry(cluster)? {
+                FatEntry::Cluster(next) => cluster = next,
+                _ => return Ok(()),
+            }
+        }
+        let mut pos = offset as usize % bytes_per_cluster;
+        let mut size = size as usize;
+        loop {
+            let first = cluster;
+            let mut len = bytes_per_cluster - pos;
+            while len < size {
+                match fat.get_entry(cluster)? {
+                    FatEntry::Cluster(next) if next == cluster + 1 => {
+                        cluster = next;
+                        len += bytes_per_cluster;
+                    }
+                    _ => break,
                 }
             }
-            start_sector_index = 0;
+            let len = min(len, size);
+            let sector = self.meta.cluster_to_sector(first) + pos / bytes_per_sector;
+            f(sector, pos % bytes_per_sector, len)?;
+            size -= len;
+            if size == 0 {
+                return Ok(());
+            }
+            pos = 0;
+            match fat.get_entry(cluster)? {
+                FatEntry::Cluster(next) => cluster = next,
+                _ => return Ok(()),
+            }
+        }
+    }
+    /// 读取从`sector`的`start`处开始的连续区域
+    /// 足够大的整扇区部分绕过缓存直接读取，首尾不完整的扇区经过缓存
+    fn read_extent(
+        &self,
+        mut sector: usize,
+        mut start: usize,
+        buf: &mut [u8],
+    ) -> Result<(), OperationError> {
+        let bytes_per_sector = self.meta.bytes_per_sector as usize;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let remain = buf.len() - pos;
+            if start == 0 && remain >= bytes_per_sector * DIRECT_IO_SECTORS {
+                let len = remain / bytes_per_sector * bytes_per_sector;
+                self.ctx.read_blocks(sector, &mut buf[pos..pos + len])?;
+                pos += len;
+                sector += len / bytes_per_sector;
+                continue;
+            }
+            let len = min(bytes_per_sector - start, remain);
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Data)?;
+            cache.read_content(|content: &Content| {
+                buf[pos..pos + len].copy_from_slice(&content.read()[start..start + len]);
+            });
+            pos += len;
+            sector += 1;
+            start = 0;
         }
-        ans
+        Ok(())
+    }
+    /// 写入从`sector`的`start`处开始的连续区域，规则同[File::read_extent]
+    fn write_extent(
+        &self,
+        mut sector: usize,
+        mut start: usize,
+        buf: &[u8],
+    ) -> Result<(), OperationError> {
+        let bytes_per_sector = self.meta.bytes_per_sector as usize;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let remain = buf.len() - pos;
+            if start == 0 && remain >= bytes_per_sector * DIRECT_IO_SECTORS {
+                let len = remain / bytes_per_sector * bytes_per_sector;
+                self.ctx.write_blocks(sector, &buf[pos..pos + len])?;
+                pos += len;
+                sector += len / bytes_per_sector;
+                continue;
+            }
+            let len = min(bytes_per_sector - start, remain);
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Data)?;
+            info!("write: {start}-{} in sector {sector}", start + len);
+            cache.write_content(|content: &mut Content| {
+                content.write()[start..start + len].copy_from_slice(&buf[pos..pos + len]);
+            });
+            pos += len;
+            sector += 1;
+            start = 0;
+        }
+        Ok(())
+    }
+    /// 找到文件簇链的最后一个簇
+    fn last_cluster(&self, fat: &Fat) -> Result<u32, OperationError> {
+        let chain = fat.get_cluster_chain(self.start_cluster()?)?;
+        Ok(*chain.last().unwrap())
     }
     /// 计算在offset处写入size个字节需要当前文件增加的簇数
     /// 并且计算新文件大小
-    fn calculate_addition_cluster(&self, offset: u32, w_size: u32) -> (usize, u32) {
+    fn calculate_addition_cluster(
+        &self,
+        offset: u32,
+        w_size: u32,
+    ) -> Result<(usize, u32), OperationError> {
         let mut need_cluster = (offset + w_size) / self.meta.bytes_per_cluster();
-        if (offset + w_size) % self.meta.bytes_per_cluster() != 0 {
+        if !(offset + w_size).is_multiple_of(self.meta.bytes_per_cluster()) {
             need_cluster += 1;
         }
         // 计算文件已经占用的簇数
-        let size = self.size();
+        let size = self.read_size()?;
         let mut used_cluster = size / self.meta.bytes_per_cluster();
         if size % self.meta.bytes_per_cluster() != 0 || size == 0 {
             used_cluster += 1;
@@ -761,140 +1278,360 @@ impl File {
         // 计算需要增加的簇数
         let need_cluster = need_cluster.saturating_sub(used_cluster);
         let new_size = max(size, offset + w_size);
-        (need_cluster as usize, new_size)
+        Ok((need_cluster as usize, new_size))
+    }
+
+    /// 在簇链末尾追加count个簇，返回新的最后一个簇
+    fn extend_cluster_chain(
+        &self,
+        fat: &mut Fat,
+        last: u32,
+        count: usize,
+    ) -> Result<u32, OperationError> {
+        let mut begin = last; // 原文件的最后一个簇
+        for _ in 0..count {
+            let cluster = fat.alloc_cluster()?; // 分配簇
+            fat.set_entry(begin, FatEntry::Cluster(cluster), DirEntryType::File)?; // 将原文件的最后一个簇指向新分配的簇
+            begin = cluster; // 更新原文件的最后一个簇
+        }
+        // 最后一个簇指向结束标志
+        fat.set_entry(begin, FatEntry::Eof, DirEntryType::File)?;
+        Ok(begin)
+    }
+
+    /// 将[offset, offset+size)范围内的数据清零
+    fn fill_zero(&self, fat: &Fat, offset: u32, size: u32) -> Result<(), OperationError> {
+        self.for_each_sector(fat, offset, size, |sector, start, len| {
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Data)?;
+            cache.write_content(|content: &mut Content| {
+                content.write()[start..start + len].fill(0);
+            });
+            Ok(())
+        })
     }
 
-    fn update_size(&self, size: u32) {
-        let cache = get_block_cache_by_id(self.address.0);
-        cache.write(0, |content: &mut Content| {
+    /// 从短目录项中读取文件大小
+    fn read_size(&self) -> Result<u32, OperationError> {
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        info!("file at :({},{})", self.address.0, self.address.1);
+        let mut size = 0;
+        cache.read_content(|content: &Content| {
+            let content = content.read();
+            size = u32_from_le_bytes(&content[self.address.1 + 28..self.address.1 + 32]);
+        });
+        Ok(size)
+    }
+
+    fn update_size(&self, size: u32) -> Result<(), OperationError> {
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        cache.write_content(|content: &mut Content| {
             let content = content.write();
             let size = size.to_le_bytes();
             content[self.address.1 + 28..self.address.1 + 32].copy_from_slice(&size);
         });
+        // 修改大小意味着文件被修改
+        self.update_modify_time()
+    }
+
+    /// 从短目录项中读取起始簇号
+    /// 空文件的第一个簇可能在之后才分配，所以不在File中保存
+    fn start_cluster(&self) -> Result<u32, OperationError> {
+        Ok(self.short_entry()?.start_cluster())
+    }
+
+    /// 为没有分配簇的空文件分配第一个簇，并写入短目录项
+    fn alloc_first_cluster(&self, fat: &mut Fat) -> Result<u32, OperationError> {
+        let cluster = fat.alloc_cluster()?;
+        fat.set_entry(cluster, FatEntry::Eof, DirEntryType::File)?;
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        cache.write(self.address.1, |entry: &mut EntryBytes| {
+            let mut short_entry = ShortEntry::from_buffer(entry);
+            short_entry.set_start_cluster(cluster);
+            entry.copy_from_slice(&short_entry.to_buffer());
+        });
+        Ok(cluster)
+    }
+
+    /// 读取文件的短目录项
+    fn short_entry(&self) -> Result<ShortEntry, OperationError> {
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        Ok(cache.read(self.address.1, |entry: &EntryBytes| {
+            ShortEntry::from_buffer(entry)
+        }))
+    }
+
+    /// 更新修改时间与访问日期
+    fn update_modify_time(&self) -> Result<(), OperationError> {
+        let now = self.ctx.now();
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        cache.write(self.address.1, |entry: &mut EntryBytes| {
+            let mut short_entry = ShortEntry::from_buffer(entry);
+            short_entry.set_modify_time(now);
+            entry.copy_from_slice(&short_entry.to_buffer());
+        });
+        Ok(())
+    }
+
+    /// 更新访问日期
+    /// 日期没有变化时不写入，避免每次读取都产生脏块
+    fn update_access_date(&self) -> Result<(), OperationError> {
+        let now = self.ctx.now();
+        let mut short_entry = self.short_entry()?;
+        if short_entry.access_date().fat_date() == now.fat_date() {
+            return Ok(());
+        }
+        short_entry.set_access_date(now);
+        let cache = self
+            .ctx
+            .get_block_cache_by_id(self.address.0, CacheClass::Dir)?;
+        cache.write(self.address.1, |entry: &mut EntryBytes| {
+            entry.copy_from_slice(&short_entry.to_buffer());
+        });
+        Ok(())
+    }
+
+    /// 文件的创建时间，精确到10ms
+    pub fn created(&self) -> Result<DateTime, OperationError> {
+        Ok(self.short_entry()?.create_time())
+    }
+    /// 文件的最后修改时间，精确到2s
+    pub fn modified(&self) -> Result<DateTime, OperationError> {
+        Ok(self.short_entry()?.modify_time())
+    }
+    /// 文件的最后访问日期，fat32不记录访问的具体时间
+    pub fn accessed(&self) -> Result<DateTime, OperationError> {
+        Ok(self.short_entry()?.access_date())
+    }
+    /// 文件的元数据
+    pub fn metadata(&self) -> Result<Metadata, OperationError> {
+        let short_entry = self.short_entry()?;
+        let cluster_count = self.fat.read().chain_len(short_entry.start_cluster())?;
+        Ok(Metadata::new(
+            &short_entry,
+            cluster_count,
+            self.meta.bytes_per_cluster(),
+        ))
     }
 }
 
 impl FileLike for File {
     type Error = OperationError;
 
-    fn read(&self, offset: u32, size: u32) -> Result<Vec<u8>, Self::Error> {
+    fn read(&self, offset: u64, size: u64) -> Result<Vec<u8>, Self::Error> {
         // 偏移量大于文件大小则直接返回空
-        let file_size = self.size();
+        let file_size = self.read_size()? as u64;
         if offset >= file_size {
             return Ok(Vec::new());
         }
-        // 最多只能读取文件的大小
-        let mut size = min(file_size, size);
-        info!("read file at offset:{}, size:{}", offset, size);
-        let mut data = Vec::new();
-        // 提前分配空间
-        data.reserve(size as usize);
+        // 最多只能读取到文件末尾
+        let mut data = vec![0; min(file_size - offset, size) as usize];
+        let len = self.read_at(offset, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
 
+    fn write(&self, offset: u64, data: &[u8]) -> Result<u64, Self::Error> {
+        self.write_at(offset, data).map(|len| len as u64)
+    }
+
+    /// 从offset处读取数据到buf中，返回读取的字节数
+    /// 数据直接从扇区缓存复制到buf
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error> {
         // 拿到fat的读锁，防止其它线程修改fat表
         let fat = self.fat.read();
-        // 文件所占的簇链
-        let cluster_chain = fat.get_cluster_chain(self.start_cluster);
-        // 计算需要读取的扇区
-        let sectors = self.calculate_sectors_without_alloc(offset, size, &cluster_chain);
-        let mut offset = offset;
-
-        for i in sectors {
-            let cache = get_block_cache_by_id(i);
-            cache.read(0, |content: &Content| {
-                let content = content.read();
-                let start = (offset % self.meta.bytes_per_sector as u32) as usize;
-                let end = min(start + size as usize, self.meta.bytes_per_sector as usize);
-                data.extend_from_slice(&content[start..end]);
-                size -= (end - start) as u32;
-                offset += (end - start) as u32;
-                if size == 0 {
-                    return;
-                }
-            });
+        // 偏移量大于文件大小则直接返回0
+        let file_size = self.read_size()?;
+        if offset >= file_size as u64 {
+            return Ok(0);
         }
-        Ok(data)
+        // 此时offset一定小于4G
+        let offset = offset as u32;
+        // 最多只能读取到文件末尾
+        let size = min((file_size - offset) as u64, buf.len() as u64) as u32;
+        info!("read file at offset:{}, size:{}", offset, size);
+        let mut pos = 0;
+        self.for_each_extent(&fat, offset, size, |sector, start, len| {
+            self.read_extent(sector, start, &mut buf[pos..pos + len])?;
+            pos += len;
+            Ok(())
+        })?;
+        self.update_access_date()?;
+        Ok(pos)
     }
 
-    /// 向文件中写入数据
+    /// 将buf写入文件的offset处，返回写入的字节数
     /// 1. 如果文件大小不够则分配簇
     /// 2. 如果文件大小够则直接写入
-    fn write(&self, offset: u32, data: &[u8]) -> Result<u32, Self::Error> {
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, Self::Error> {
+        // 写入0个字节不改变文件
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // fat32的文件大小最多为4G-1
+        let end = offset.saturating_add(buf.len() as u64);
+        if end > MAX_FILE_SIZE {
+            return Err(OperationError::FileTooLarge);
+        }
+        let offset = offset as u32;
         // 拿到fat的写锁，防止其它线程修改fat表
         let mut fat = self.fat.write();
         // 计算额外需要的簇数
-        let (addition, new_size) = self.calculate_addition_cluster(offset, data.len() as u32);
+        let (addition, new_size) = self.calculate_addition_cluster(offset, buf.len() as u32)?;
         info!("addition :{}, new_size :{}", addition, new_size);
-        info!("file_start_cluster: {}", self.start_cluster);
-        // 计算文件已经分配的簇
-        let mut old_cluster_chain = fat.get_cluster_chain(self.start_cluster);
-        info!("old_cluster_chain :{:?}", old_cluster_chain);
+        // 其它系统创建的空文件没有分配簇，先分配第一个簇
+        // calculate_addition_cluster已经把它计算在内
+        if self.start_cluster()? < 2 {
+            self.alloc_first_cluster(&mut fat)?;
+        }
+        info!("file_start_cluster: {}", self.start_cluster()?);
         // 开始分配额外的簇
-        let mut begin = *old_cluster_chain.last().unwrap(); // 原文件的最后一个簇
-        for _ in 0..addition {
-            let cluster = fat
-                .alloc_cluster()
-                .map_or(Err(OperationError::NoEnoughSpace), |cluster| Ok(cluster))?; // 分配簇
-            old_cluster_chain.push(cluster); // 将新分配的簇加入队列
-            fat.set_entry(begin, FatEntry::Cluster(cluster), DirEntryType::File); // 将原文件的最后一个簇指向新分配的簇
-            begin = cluster; // 更新原文件的最后一个簇
+        if addition > 0 {
+            let last = self.last_cluster(&fat)?;
+            self.extend_cluster_chain(&mut fat, last, addition)?;
         }
-        // 最后一个簇指向结束标志
-        fat.set_entry(begin, FatEntry::Eof, DirEntryType::File);
-        info!("new_cluster_chain :{:?}", old_cluster_chain);
-
-        // 找到offset位于的扇区位置
-        let sectors =
-            self.calculate_sectors_without_alloc(offset, data.len() as u32, &old_cluster_chain);
-        let mut offset = offset;
-        let mut size = data.len() as u32;
-        let mut data_start = 0;
-        for i in sectors {
-            let cache = get_block_cache_by_id(i);
-            cache.write(0, |content: &mut SectorData| {
-                let start = (offset % self.meta.bytes_per_sector as u32) as usize;
-                let end = min(start + size as usize, self.meta.bytes_per_sector as usize);
-                info!("write: {start}-{end} in sector {i}");
-                content[start..end].copy_from_slice(&data[data_start..data_start + (end - start)]);
-                size -= (end - start) as u32;
-                offset += (end - start) as u32;
-                data_start += end - start;
-            });
-            if size == 0 {
-                break;
-            }
+        // 在文件末尾之后写入时，原来的末尾到offset之间需要清零
+        // 这些簇可能是其它文件释放的，不能暴露旧数据
+        let size = self.read_size()?;
+        if offset > size {
+            self.fill_zero(&fat, size, offset - size)?;
         }
-        // 更新文件大小 todo!()
-        self.update_size(new_size);
-        Ok(data.len() as u32)
+        let mut pos = 0;
+        self.for_each_extent(&fat, offset, buf.len() as u32, |sector, start, len| {
+            self.write_extent(sector, start, &buf[pos..pos + len])?;
+            pos += len;
+            Ok(())
+        })?;
+        // 更新文件大小
+        self.update_size(new_size)?;
+        Ok(buf.len())
     }
 
     /// 清空文件内容
     /// 释放所有簇
-    fn clear(&self) {
+    fn clear(&self) -> Result<(), Self::Error> {
         trace!("clear file");
-        let fat = self.fat.write();
-        let cluster_chain = fat.get_cluster_chain(self.start_cluster);
-        trace!("clear file, cluster_chain: {:?}", cluster_chain);
-        for &i in cluster_chain.iter().skip(1) {
-            fat.set_entry(i, FatEntry::Free, DirEntryType::File);
-        } // 跳过了第一个簇
-          // 将第一个簇指向结束标志
-        fat.set_entry(self.start_cluster, FatEntry::Eof, DirEntryType::File);
+        // 保留第一个簇，释放其余的簇
+        let start_cluster = self.start_cluster()?;
+        if start_cluster != 0 {
+            self.fat
+                .write()
+                .truncate_chain(start_cluster, DirEntryType::File)?;
+        }
         // 更新文件大小
-        self.update_size(0);
+        self.update_size(0)
     }
-    fn size(&self) -> u32 {
-        let cache = get_block_cache_by_id(self.address.0);
-        info!("file at :({},{})", self.address.0, self.address.1);
-        let mut size = 0;
-        cache.read(0, |content: &Content| {
-            let content = content.read();
-            size = u32_from_le_bytes(&content[self.address.1 + 28..self.address.1 + 32]);
-        });
-        size
+    /// 修改文件大小
+    /// 1. 缩小时释放多余的簇，至少保留第一个簇
+    /// 2. 扩大时分配新的簇，并将新增的部分清零
+    fn set_len(&self, new_len: u64) -> Result<(), Self::Error> {
+        if new_len > MAX_FILE_SIZE {
+            return Err(OperationError::FileTooLarge);
+        }
+        let new_len = new_len as u32;
+        let mut fat = self.fat.write();
+        let mut start_cluster = self.start_cluster()?;
+        // 其它系统创建的空文件没有分配簇
+        if start_cluster < 2 {
+            if new_len == 0 {
+                return self.update_size(0);
+            }
+            start_cluster = self.alloc_first_cluster(&mut fat)?;
+        }
+        let size = self.read_size()?;
+        let cluster_chain = fat.get_cluster_chain(start_cluster)?;
+        let bytes_per_cluster = self.meta.bytes_per_cluster() as u64;
+        let need_cluster = max(1, (new_len as u64).div_ceil(bytes_per_cluster)) as usize;
+        info!(
+            "set_len from {} to {}, clusters: {} -> {}",
+            size,
+            new_len,
+            cluster_chain.len(),
+            need_cluster
+        );
+        if need_cluster < cluster_chain.len() {
+            fat.truncate_chain(cluster_chain[need_cluster - 1], DirEntryType::File)?;
+        } else if need_cluster > cluster_chain.len() {
+            let addition = need_cluster - cluster_chain.len();
+            self.extend_cluster_chain(&mut fat, *cluster_chain.last().unwrap(), addition)?;
+        }
+        if new_len > size {
+            self.fill_zero(&fat, size, new_len - size)?;
+        }
+        self.update_size(new_len)
+    }
+    fn size(&self) -> Result<u64, Self::Error> {
+        Ok(self.read_size()? as u64)
+    }
+}
+
+/// 文件或目录的元数据
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// 目录项属性
+    pub attr: EntryFlags,
+    /// 创建时间，精确到10ms
+    pub created: DateTime,
+    /// 最后修改时间，精确到2s
+    pub modified: DateTime,
+    /// 最后访问日期
+    pub accessed: DateTime,
+    /// 文件的逻辑大小，目录没有记录大小，与allocated_size相同
+    pub size: u64,
+    /// 在磁盘上实际占用的大小
+    pub allocated_size: u64,
+    /// 起始簇号
+    pub start_cluster: u32,
+    /// 占用的簇数
+    pub cluster_count: usize,
+    /// 8.3短文件名
+    pub short_name: String,
+}
+
+impl Metadata {
+    fn new(short_entry: &ShortEntry, cluster_count: usize, bytes_per_cluster: u32) -> Self {
+        let attr = *short_entry.attr();
+        let allocated_size = cluster_count as u64 * bytes_per_cluster as u64;
+        let size = if attr.contains(EntryFlags::DIRECTORY) {
+            allocated_size
+        } else {
+            short_entry.file_size() as u64
+        };
+        Self {
+            attr,
+            created: short_entry.create_time(),
+            modified: short_entry.modify_time(),
+            accessed: short_entry.access_date(),
+            size,
+            allocated_size,
+            start_cluster: short_entry.start_cluster(),
+            cluster_count,
+            short_name: short_entry.alias(),
+        }
+    }
+    pub fn is_dir(&self) -> bool {
+        self.attr.contains(EntryFlags::DIRECTORY)
+    }
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
     }
 }
 
+/// fat32中文件的最大长度(4G-1)
+pub const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+/// 连续的整扇区数量达到该值时绕过块缓存直接读写设备
+const DIRECT_IO_SECTORS: usize = 8;
+
 #[derive(PartialOrd, PartialEq, Debug)]
 pub enum DirEntryType {
     Dot,
@@ -933,8 +1670,51 @@ pub enum OperationError {
     DirExist,
     DirNotFound,
     OffsetOutOfSize,
+    /// 文件大小超过了4G-1
+    FileTooLarge,
     InvalidDirName,
+    /// 名称为空或者包含不合法的字符
+    InvalidName,
+    /// 名称超过255个UTF-16码元
+    NameTooLong,
     NotFound,
+    /// 目录不为空，不能被替换
+    DirNotEmpty,
+    /// 目录不能移动到自己或者自己的子目录中
+    InvalidMove,
+    /// 目录中无法再生成不重复的短文件名
+    NoShortName,
+    /// 引导扇区不是合法的fat32卷
+    InvalidBpb(BpbError),
+    /// fs_info扇区的签名不正确
+    InvalidFsInfo,
+    /// 簇链中出现空闲簇、坏簇、超出范围的簇号或者环
+    BadClusterChain,
+    /// 块设备读写失败
+    Io(DeviceError),
+    /// 块缓存已满且所有缓存块都在使用中
+    CacheFull,
+    /// 路径中的某一部分出错
+    Path {
+        /// 出错的部分
+        component: String,
+        error: Box<OperationError>,
+    },
+}
+
+impl From<DeviceError> for OperationError {
+    fn from(err: DeviceError) -> Self {
+        OperationError::Io(err)
+    }
+}
+
+impl From<CacheError> for OperationError {
+    fn from(err: CacheError) -> Self {
+        match err {
+            CacheError::Io(err) => OperationError::Io(err),
+            CacheError::Full => OperationError::CacheFull,
+        }
+    }
 }
 
 impl Display for OperationError {
@@ -948,48 +1728,120 @@ impl Error for OperationError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::CacheOptions;
+    use crate::device::{wrap_device, BlockDevice};
+    use crate::layout::FsInfo;
+    use crate::time::DefaultTimeProvider;
+
+    struct NullDevice;
+    impl BlockDevice for NullDevice {
+        fn read(&self, _block: usize, _buf: &mut [u8]) -> Result<usize, ()> {
+            Ok(0)
+        }
+        fn write(&self, _block: usize, _buf: &[u8]) -> Result<usize, ()> {
+            Ok(0)
+        }
+        fn flush(&self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    struct BadDevice;
+    impl BlockDevice for BadDevice {
+        type Error = &'static str;
+        fn read(&self, _block: usize, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Err("bad sector")
+        }
+        fn write(&self, _block: usize, _buf: &[u8]) -> Result<usize, Self::Error> {
+            Err("bad sector")
+        }
+        fn flush(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn make_ctx() -> Arc<FsContext> {
+        Arc::new(FsContext::new(
+            wrap_device(NullDevice),
+            512,
+            CacheOptions::with_capacity(1),
+            Arc::new(DefaultTimeProvider),
+        ))
+    }
     fn make_dir() -> Dir {
+        let ctx = make_ctx();
         Dir {
             start_cluster: 0,
             address: (0, 0),
+            short_name: String::new(),
             meta: Arc::new(MetaData::default()),
-            fat: Arc::new(RwLock::new(Fat::empty())),
+            fat: Arc::new(RwLock::new(Fat::empty(ctx.clone()))),
+            ctx,
             sub_dirs: Arc::new(Default::default()),
             files: Arc::new(Default::default()),
         }
     }
     #[test]
-    fn test_name_to_short_name() {
-        let dir = make_dir();
-        let name1 = "hello1234.txt";
-        let short_name = dir.name_to_short_name(name1, DirEntryType::File);
-        assert_eq!("hello1~1.txt", short_name);
-        dir.files
-            .write()
-            .insert("hello1234.txt".to_string(), File::empty());
-        let short_name = dir.name_to_short_name(name1, DirEntryType::File);
-        assert_eq!("hello1~2.txt", short_name);
-        let short_name = dir.name_to_short_name(name1, DirEntryType::Dir);
-        assert_eq!("hello1~1.txt", short_name);
-        dir.sub_dirs.write().insert(name1.to_string(), dir.clone());
-        let short_name = dir.name_to_short_name(name1, DirEntryType::Dir);
-        assert_eq!("hello1~2.txt", short_name);
+    fn test_find_key() {
+        let mut map = BTreeMap::new();
+        map.insert("readme.txt".to_string(), "README.TXT".to_string());
+        map.insert("Program Files".to_string(), "PROGRA~1".to_string());
+        map.insert("..".to_string(), "PARENT".to_string());
+        let find = |name| find_key(&map, name, |short: &String| short);
+        assert_eq!(find("readme.txt").unwrap(), "readme.txt");
+        assert_eq!(find("README.TXT").unwrap(), "readme.txt");
+        assert_eq!(find("program files").unwrap(), "Program Files");
+        assert_eq!(find("progra~1").unwrap(), "Program Files");
+        assert!(find("PARENT").is_none());
+        assert!(find("readme").is_none());
     }
     #[test]
     fn test_make_entry() {
         let dir = make_dir();
         let name1 = "hello1234.txt";
-        let short_name = dir.name_to_short_name(name1, DirEntryType::File);
+        let short_name = generate_short_name(name1, |_| false).unwrap();
         let (short_entry, full_long_entry) = dir
             .make_entry(name1, &short_name, 0, DirEntryType::File)
             .unwrap();
         assert_eq!(short_entry.start_cluster(), 0);
-        assert_eq!(short_entry.filename(), short_name.to_uppercase());
+        assert_eq!(short_entry.filename(), "HELLO1~1.TXT");
         assert_eq!(short_entry.attr(), &EntryFlags::ARCHIVE);
         assert_eq!(full_long_entry.filename(), name1);
         assert_eq!(full_long_entry.len(), 1);
         full_long_entry.iter().for_each(|long_entry| {
             assert_eq!(long_entry.check_sum(), short_entry.check_sum());
-        })
+        });
+        // 可以用8.3表示的名称不需要长目录项
+        let name2 = "readme.txt";
+        let short_name = generate_short_name(name2, |_| false).unwrap();
+        let (short_entry, full_long_entry) = dir
+            .make_entry(name2, &short_name, 0, DirEntryType::File)
+            .unwrap();
+        assert_eq!(short_entry.filename(), name2);
+        assert_eq!(full_long_entry.len(), 0);
+        // 短文件名被占用时仍然需要长目录项
+        let short_name = generate_short_name(name2, |x| *x == short_name).unwrap();
+        let (short_entry, full_long_entry) = dir
+            .make_entry(name2, &short_name, 0, DirEntryType::File)
+            .unwrap();
+        assert_eq!(short_entry.filename(), "README~1.TXT");
+        assert_eq!(full_long_entry.filename(), name2);
+    }
+    #[test]
+    fn test_device_error() {
+        let ctx = Arc::new(FsContext::new(
+            wrap_device(BadDevice),
+            512,
+            CacheOptions::with_capacity(1),
+            Arc::new(DefaultTimeProvider),
+        ));
+        let meta = MetaData {
+            bytes_per_sector: 512,
+            sectors_per_cluster: 1,
+            ..Default::default()
+        };
+        let fat = Fat::new(Arc::new(meta), Arc::new(FsInfo::new(&[0; 512])), ctx).unwrap();
+        let ans = fat.get_entry(2);
+        assert!(matches!(ans, Err(OperationError::Io(_))));
     }
 }
@@ -1,8 +1,11 @@
+use crate::name::{short_name_to_string, ShortName};
+use crate::time::DateTime;
 use crate::utils::{u16_from_le_bytes, u32_from_le_bytes};
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use bitflags::bitflags;
 use core::cmp::min;
+use log::warn;
 bitflags! {
     pub struct EntryFlags:u8{
         const READ_ONLY = 0b0000_0001;
@@ -16,12 +19,15 @@ bitflags! {
 }
 
 /// 短目录项
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ShortEntry {
     name: [u8; 8],
     ext: [u8; 3],
     attr: EntryFlags,
-    reserved: u8,
+    /// Windows NT保存基本名和扩展名是否小写
+    case: u8,
+    /// 创建时间的10ms位
+    create_time_tenth: u8,
     create_time: u16,
     create_date: u16,
     last_access_date: u16,
@@ -32,6 +38,9 @@ pub struct ShortEntry {
     file_size: u32,
 }
 
+/// 最后一个长目录项的序号带有的标志
+const LAST_LONG_ENTRY: u8 = 0x40;
+
 #[derive(Debug)]
 pub struct FullLoongEntry {
     entries: Vec<LongEntry>,
@@ -43,43 +52,77 @@ impl FullLoongEntry {
             entries: Vec::new(),
         }
     }
+    /// 按照磁盘上的顺序加入长目录项
+    /// 遇到带有0x40标志的目录项说明开始了新的长文件名，之前的目录项是孤立的
     pub fn push(&mut self, entry: LongEntry) {
+        if entry.order & LAST_LONG_ENTRY != 0 && !self.entries.is_empty() {
+            warn!("orphan long entries: {:?}", self.filename());
+            self.entries.clear();
+        }
         self.entries.push(entry);
     }
-    pub fn filename(&self) -> String {
-        let mut filename = String::new();
-        for entry in self.entries.iter().rev() {
-            filename.push_str(&entry.filename());
+    /// 序号以及校验和是否都正确
+    /// 第一个目录项带有0x40标志，序号从n依次递减到1，校验和与短目录项相同
+    fn is_valid(&self, check_sum: u8) -> bool {
+        let Some(first) = self.entries.first() else {
+            return false;
+        };
+        let count = (first.order & !LAST_LONG_ENTRY) as usize;
+        first.order & LAST_LONG_ENTRY != 0
+            && count == self.entries.len()
+            && self.entries.iter().enumerate().all(|(index, entry)| {
+                entry.order & !LAST_LONG_ENTRY == (count - index) as u8
+                    && entry.checksum == check_sum
+            })
+    }
+    /// 属于校验和为check_sum的短目录项的长文件名
+    /// 没有长目录项或者长目录项不合法时返回空字符串，此时使用短文件名
+    pub fn checked_filename(&self, check_sum: u8) -> String {
+        if self.entries.is_empty() {
+            return String::new();
         }
-
-        filename
+        if !self.is_valid(check_sum) {
+            warn!("orphan long entries: {:?}", self.filename());
+            return String::new();
+        }
+        self.filename()
+    }
+    /// 拼接所有长目录项中的UTF-16码元，不合法的部分用U+FFFD代替
+    pub fn filename(&self) -> String {
+        let units = self
+            .entries
+            .iter()
+            .rev()
+            .flat_map(|entry| entry.units())
+            .collect::<Vec<u16>>();
+        String::from_utf16_lossy(&units)
     }
     pub fn clear(&mut self) {
         self.entries.clear();
     }
+    /// 按照UTF-16码元每13个拆分到一个长目录项中
+    /// 最后一个长目录项的序号为0x40|index，与磁盘上一样倒序保存
     pub fn from_file_name(filename: &str, check_sum: u8) -> Self {
-        let mut entries = Vec::new();
-        let mut filename = filename.to_string();
-        let mut index = 1;
-        while filename.len() > 13 {
-            let name = filename.split_off(13);
-            let entry = LongEntry::new(&filename, index, check_sum);
-            entries.push(entry);
-            filename = name;
-            index += 1;
-        }
-        // 不满足13个字符的最后一个entry
-        // 其order为0x40|index
-        // 需要在字符串后面添加0x00
-        filename.push(0x00 as char);
-        let entry = LongEntry::new(&filename, 0x40 | index, check_sum);
-        entries.push(entry);
+        let units = filename.encode_utf16().collect::<Vec<u16>>();
+        let count = units.len().div_ceil(13);
+        let entries = units
+            .chunks(13)
+            .enumerate()
+            .rev()
+            .map(|(index, chunk)| {
+                let mut order = index as u8 + 1;
+                if index + 1 == count {
+                    order |= LAST_LONG_ENTRY;
+                }
+                LongEntry::new(chunk, order, check_sum)
+            })
+            .collect();
         Self { entries }
     }
     pub fn len(&self) -> usize {
         self.entries.len()
     }
-    pub fn iter(&self) -> core::slice::Iter<LongEntry> {
+    pub fn iter(&self) -> core::slice::Iter<'_, LongEntry> {
         self.entries.iter()
     }
 }
@@ -98,7 +141,8 @@ pub struct LongEntry {
 }
 
 impl ShortEntry {
-    pub fn new(name: &str, attr: EntryFlags, cluster: u32) -> Self {
+    #[allow(unused)]
+    pub fn new(name: &str, attr: EntryFlags, cluster: u32, now: DateTime) -> Self {
         // 长文件名需要截断并全部转换为大写
         let (name, ext) = if name == "." || name == ".." {
             (name.to_string(), String::new())
@@ -113,35 +157,36 @@ impl ShortEntry {
             let name = name[..dot_index].to_uppercase();
             (name, ext)
         };
-        let mut buffer = [0u8; 32];
-        // 用空格填充
-        buffer[0..8].fill(0x20);
-        buffer[8..11].fill(0x20);
+        let mut short_name = [0x20u8; 11];
         // 写入文件名
         let min_ = min(name.len(), 8);
-        buffer[0..min_].copy_from_slice(&name.as_bytes()[0..min_]);
+        short_name[0..min_].copy_from_slice(&name.as_bytes()[0..min_]);
         // 写入扩展名
         let min_ = min(ext.len(), 3);
-        buffer[8..8 + min_].copy_from_slice(&ext.as_bytes()[0..min_]);
+        short_name[8..8 + min_].copy_from_slice(&ext.as_bytes()[0..min_]);
+        Self::from_short_name(&short_name, attr, cluster, now)
+    }
+    /// 使用已经生成好的11字节短文件名创建短目录项
+    pub fn from_short_name(
+        short_name: &ShortName,
+        attr: EntryFlags,
+        cluster: u32,
+        now: DateTime,
+    ) -> Self {
+        let mut buffer = [0u8; 32];
+        buffer[0..11].copy_from_slice(short_name);
         buffer[11] = attr.bits();
         // 写入起始簇号
         buffer[26..28].copy_from_slice(&cluster.to_le_bytes()[0..2]);
         buffer[20..22].copy_from_slice(&cluster.to_le_bytes()[2..4]);
 
-        //写入创建的时间
-        buffer[13] = 124;
-        buffer[14] = 188;
-        buffer[15] = 108;
-        buffer[16] = 106;
-        buffer[17] = 85;
-        buffer[18] = 106;
-        buffer[19] = 85;
-        buffer[22] = 188;
-        buffer[23] = 108;
-        buffer[24] = 106;
-        buffer[25] = 85;
-
-        ShortEntry::from_buffer(&buffer)
+        let mut entry = ShortEntry::from_buffer(&buffer);
+        // 写入创建的时间
+        entry.create_time_tenth = now.fat_tenth();
+        entry.create_time = now.fat_time();
+        entry.create_date = now.fat_date();
+        entry.set_modify_time(now);
+        entry
     }
     /// 从字节数组中解析出短目录项
     pub fn from_buffer(buffer: &[u8]) -> Self {
@@ -150,10 +195,11 @@ impl ShortEntry {
         name.copy_from_slice(&buffer[0..8]);
         ext.copy_from_slice(&buffer[8..11]);
         let attr = EntryFlags::from_bits_truncate(buffer[11]);
-        let reserved = buffer[12];
-        let create_time = u16_from_le_bytes(&buffer[13..=14]);
-        let create_date = u16_from_le_bytes(&buffer[15..=16]);
-        let last_access_date = u16_from_le_bytes(&buffer[17..=18]);
+        let case = buffer[12];
+        let create_time_tenth = buffer[13];
+        let create_time = u16_from_le_bytes(&buffer[14..=15]);
+        let create_date = u16_from_le_bytes(&buffer[16..=17]);
+        let last_access_date = u16_from_le_bytes(&buffer[18..=19]);
         let cluster_high = u16_from_le_bytes(&buffer[20..=21]);
         let modify_time = u16_from_le_bytes(&buffer[22..=23]);
         let modify_date = u16_from_le_bytes(&buffer[24..=25]);
@@ -163,7 +209,8 @@ impl ShortEntry {
             name,
             ext,
             attr,
-            reserved,
+            case,
+            create_time_tenth,
             create_time,
             create_date,
             last_access_date,
@@ -179,10 +226,11 @@ impl ShortEntry {
         buffer[0..8].copy_from_slice(&self.name);
         buffer[8..11].copy_from_slice(&self.ext);
         buffer[11] = self.attr.bits();
-        buffer[12] = self.reserved;
-        buffer[13..15].copy_from_slice(&self.create_time.to_le_bytes());
-        buffer[15..17].copy_from_slice(&self.create_date.to_le_bytes());
-        buffer[17..19].copy_from_slice(&self.last_access_date.to_le_bytes());
+        buffer[12] = self.case;
+        buffer[13] = self.create_time_tenth;
+        buffer[14..16].copy_from_slice(&self.create_time.to_le_bytes());
+        buffer[16..18].copy_from_slice(&self.create_date.to_le_bytes());
+        buffer[18..20].copy_from_slice(&self.last_access_date.to_le_bytes());
         buffer[20..22].copy_from_slice(&self.cluster_high.to_le_bytes());
         buffer[22..24].copy_from_slice(&self.modify_time.to_le_bytes());
         buffer[24..26].copy_from_slice(&self.modify_date.to_le_bytes());
@@ -190,59 +238,88 @@ impl ShortEntry {
         buffer[28..32].copy_from_slice(&self.file_size.to_le_bytes());
         buffer
     }
-    /// 去掉0x20所占的字符
+    /// 去掉末尾0x20所占的字符，OEM字符按照代码页437解码，并按照大小写标志转换
     pub fn filename(&self) -> String {
-        let mut name = String::new();
-        for &byte in self.name.iter() {
-            if byte == 0x20 || byte == 0x00 {
-                break;
-            }
-            name.push(byte as char);
-        }
-        if self.ext[0] != 0x20 {
-            name.push('.');
-            for &byte in self.ext.iter() {
-                if byte == 0x20 || byte == 0x00 {
-                    break;
-                }
-                name.push(byte as char);
-            }
-        }
-        name
+        short_name_to_string(&self.short_name(), self.case)
+    }
+    /// 8.3别名，不考虑大小写标志
+    pub fn alias(&self) -> String {
+        short_name_to_string(&self.short_name(), 0)
+    }
+    /// 11字节的短文件名
+    pub fn short_name(&self) -> ShortName {
+        let mut short_name = [0u8; 11];
+        short_name[0..8].copy_from_slice(&self.name);
+        short_name[8..11].copy_from_slice(&self.ext);
+        short_name
     }
 
     /// 计算短文件名的校验和
     pub fn check_sum(&self) -> u8 {
         let mut sum = 0u8;
-        for &byte in self.name.iter() {
-            sum = ((sum & 1) << 7) + (sum >> 1) + byte;
+        for &byte in self.name.iter().chain(self.ext.iter()) {
+            sum = ((sum & 1) << 7 | sum >> 1).wrapping_add(byte);
         }
-        for &byte in self.ext.iter() {
-            sum = ((sum & 1) << 7) + (sum >> 1) + byte;
-        }
-        sum as u8
+        sum
+    }
+
+    /// 重命名时替换短文件名，其它字段保持不变
+    pub fn set_short_name(&mut self, short_name: &ShortName) {
+        self.name.copy_from_slice(&short_name[0..8]);
+        self.ext.copy_from_slice(&short_name[8..11]);
+    }
+    pub fn set_start_cluster(&mut self, cluster: u32) {
+        self.cluster_high = (cluster >> 16) as u16;
+        self.cluster_low = cluster as u16;
+    }
+    /// 设置大小写标志
+    pub fn set_case(&mut self, case: u8) {
+        self.case = case;
     }
 
-    #[allow(unused)]
     pub fn attr(&self) -> &EntryFlags {
         &self.attr
     }
     pub fn start_cluster(&self) -> u32 {
         u32::from(self.cluster_high) << 16 | u32::from(self.cluster_low)
     }
-    #[allow(unused)]
     pub fn file_size(&self) -> u32 {
         self.file_size
     }
+    /// 创建时间，精确到10ms
+    pub fn create_time(&self) -> DateTime {
+        DateTime::from_fat(self.create_date, self.create_time, self.create_time_tenth)
+    }
+    /// 最后修改时间，精确到2s
+    pub fn modify_time(&self) -> DateTime {
+        DateTime::from_fat(self.modify_date, self.modify_time, 0)
+    }
+    /// 最后访问日期，没有时间部分
+    pub fn access_date(&self) -> DateTime {
+        DateTime::from_fat(self.last_access_date, 0, 0)
+    }
+    /// 写入数据时更新修改时间，同时更新访问日期
+    pub fn set_modify_time(&mut self, now: DateTime) {
+        self.modify_time = now.fat_time();
+        self.modify_date = now.fat_date();
+        self.set_access_date(now);
+    }
+    pub fn set_access_date(&mut self, now: DateTime) {
+        self.last_access_date = now.fat_date();
+    }
 }
 
 impl LongEntry {
-    pub fn new(name: &str, order: u8, checksum: u8) -> Self {
+    /// name最多13个UTF-16码元，不足时以0x0000结尾，之后用0xFFFF填充
+    pub fn new(name: &[u16], order: u8, checksum: u8) -> Self {
         let mut name1 = [0u16; 5];
         let mut name2 = [0u16; 6];
         let mut name3 = [0u16; 2];
-        let mut utf16 = name.encode_utf16().collect::<Vec<u16>>();
-        utf16.resize(13, 0xFFFF);
+        let mut utf16 = [0xFFFFu16; 13];
+        utf16[..name.len()].copy_from_slice(name);
+        if name.len() < 13 {
+            utf16[name.len()] = 0x0000;
+        }
 
         name1.copy_from_slice(&utf16[0..5]);
         name2.copy_from_slice(&utf16[5..11]);
@@ -296,21 +373,15 @@ impl LongEntry {
             name3,
         }
     }
-    pub fn filename(&self) -> String {
-        let s_name = [
-            self.name1.as_slice(),
-            self.name2.as_slice(),
-            self.name3.as_slice(),
-        ]
-        .iter()
-        .flat_map(|&c| c.iter().copied())
-        .collect::<Vec<u16>>();
-        let (index, _) = s_name
+    /// 名称部分的UTF-16码元，到0x0000或者填充的0xFFFF为止
+    pub fn units(&self) -> Vec<u16> {
+        self.name1
             .iter()
-            .enumerate()
-            .rfind(|(_i, &c)| c == 0)
-            .unwrap_or((13, &0));
-        String::from_utf16(&s_name[0..index]).unwrap()
+            .chain(self.name2.iter())
+            .chain(self.name3.iter())
+            .copied()
+            .take_while(|&c| c != 0x0000 && c != 0xFFFF)
+            .collect()
     }
 
     pub fn to_buffer(&self) -> [u8; 32] {
@@ -350,7 +421,7 @@ impl LongEntry {
 
 #[test]
 fn test_short_entry_new() {
-    let short_entry = ShortEntry::new("test.txt", EntryFlags::DIRECTORY, 0);
+    let short_entry = ShortEntry::new("test.txt", EntryFlags::DIRECTORY, 0, DateTime::EPOCH);
     assert_eq!(short_entry.filename(), "TEST.TXT");
     assert_eq!(
         short_entry.name,
@@ -359,9 +430,14 @@ fn test_short_entry_new() {
     assert_eq!(short_entry.ext, [0x54u8, 0x58, 0x54].as_ref());
     assert_eq!(short_entry.file_size, 0);
     assert_eq!(short_entry.start_cluster(), 0);
-    let entry = ShortEntry::new("hhhhhhhhhhh", EntryFlags::DIRECTORY, 0);
+    let entry = ShortEntry::new("hhhhhhhhhhh", EntryFlags::DIRECTORY, 0, DateTime::EPOCH);
     assert_eq!(entry.filename(), "HHHHHHHH");
-    let entry = ShortEntry::new("hhhhhhhhhhhh.txtx", EntryFlags::DIRECTORY, 0);
+    let entry = ShortEntry::new(
+        "hhhhhhhhhhhh.txtx",
+        EntryFlags::DIRECTORY,
+        0,
+        DateTime::EPOCH,
+    );
     assert_eq!(entry.filename(), "HHHHHHHH.TXT");
 }
 
@@ -387,9 +463,34 @@ fn test_short_entry_from_buffer() {
     assert_eq!(short_entry.start_cluster(), 0);
 }
 
+#[test]
+fn test_short_entry_time() {
+    let now = DateTime {
+        year: 2023,
+        month: 4,
+        day: 15,
+        hour: 13,
+        minute: 45,
+        second: 31,
+        millisecond: 250,
+    };
+    let short_entry = ShortEntry::new("test.txt", EntryFlags::ARCHIVE, 0, now);
+    let buffer = short_entry.to_buffer();
+    assert_eq!(buffer[13], 125);
+    assert_eq!(buffer[14..16], now.fat_time().to_le_bytes());
+    assert_eq!(buffer[16..18], now.fat_date().to_le_bytes());
+    assert_eq!(buffer[18..20], now.fat_date().to_le_bytes());
+    assert_eq!(buffer[22..24], now.fat_time().to_le_bytes());
+    assert_eq!(buffer[24..26], now.fat_date().to_le_bytes());
+    let entry = ShortEntry::from_buffer(&buffer);
+    assert_eq!(entry.create_time(), now);
+    assert_eq!(entry.modify_time().second, 30);
+    assert_eq!(entry.access_date().day, 15);
+}
+
 #[test]
 fn test_short_entry_to_buffer() {
-    let short_entry = ShortEntry::new("test.txt", EntryFlags::DIRECTORY, 0);
+    let short_entry = ShortEntry::new("test.txt", EntryFlags::DIRECTORY, 0, DateTime::EPOCH);
     let buffer = short_entry.to_buffer();
     assert_eq!(
         buffer[0..11],
@@ -399,6 +500,64 @@ fn test_short_entry_to_buffer() {
 
 #[test]
 fn test_short_entry_checksum() {
-    let short_entry = ShortEntry::new("hello", EntryFlags::DIRECTORY, 0);
+    let short_entry = ShortEntry::new("hello", EntryFlags::DIRECTORY, 0, DateTime::EPOCH);
     assert_eq!(short_entry.check_sum(), 0x14);
+    // 累加时会溢出
+    let short_entry = ShortEntry::new("readme.txt", EntryFlags::ARCHIVE, 0, DateTime::EPOCH);
+    assert_eq!(short_entry.check_sum(), 0x73);
+}
+
+#[test]
+fn test_checked_filename() {
+    let name = "a long file name.txt";
+    let entry = FullLoongEntry::from_file_name(name, 0x12);
+    assert_eq!(entry.checked_filename(0x12), name);
+    // 校验和不匹配
+    assert_eq!(entry.checked_filename(0x13), "");
+    // 缺少第一个带有0x40标志的目录项
+    let mut missing = FullLoongEntry::new();
+    missing.push(LongEntry::from_buffer(&entry.entries[1].to_buffer()));
+    assert_eq!(missing.checked_filename(0x12), "");
+    // 缺少中间的目录项
+    let mut missing = FullLoongEntry::new();
+    missing.push(LongEntry::from_buffer(&entry.entries[0].to_buffer()));
+    assert_eq!(missing.checked_filename(0x12), "");
+    // 新的长文件名开始时丢弃之前孤立的目录项
+    let other = FullLoongEntry::from_file_name("other", 0x34);
+    let mut loaded = FullLoongEntry::new();
+    loaded.push(LongEntry::from_buffer(&entry.entries[0].to_buffer()));
+    loaded.push(LongEntry::from_buffer(&other.entries[0].to_buffer()));
+    assert_eq!(loaded.checked_filename(0x34), "other");
+}
+
+#[test]
+fn test_long_entry_utf16() {
+    // 13个码元正好占满一个长目录项，不需要0x0000结尾
+    let entry = FullLoongEntry::from_file_name("abcdefghijklm", 0);
+    assert_eq!(entry.len(), 1);
+    assert_eq!(entry.entries[0].order, 0x41);
+    assert_eq!(entry.entries[0].name3, [b'l' as u16, b'm' as u16]);
+    // 多字节字符按照码元计数
+    let name = "文件名文件名文件名文件名文件名.txt";
+    let entry = FullLoongEntry::from_file_name(name, 0);
+    assert_eq!(entry.len(), 2);
+    assert_eq!(entry.entries[0].order, 0x42);
+    assert_eq!(entry.entries[1].order, 1);
+    assert_eq!(entry.entries[0].units().len(), 6);
+    assert_eq!(entry.entries[0].name2[..3], [b't' as u16, 0x0000, 0xFFFF]);
+    assert_eq!(entry.entries[0].name3, [0xFFFF; 2]);
+    assert_eq!(entry.filename(), name);
+    // 代理对跨越两个长目录项
+    let name = "012345678901😀";
+    let entry = FullLoongEntry::from_file_name(name, 0);
+    assert_eq!(entry.len(), 2);
+    let mut loaded = FullLoongEntry::new();
+    for long_entry in entry.iter() {
+        loaded.push(LongEntry::from_buffer(&long_entry.to_buffer()));
+    }
+    assert_eq!(loaded.filename(), name);
+    // 单独的代理不会导致panic
+    let mut entry = FullLoongEntry::new();
+    entry.push(LongEntry::new(&[b'a' as u16, 0xD800, b'b' as u16], 0x41, 0));
+    assert_eq!(entry.filename(), "a\u{FFFD}b");
 }
@@ -1,67 +1,270 @@
-use crate::cache::{sync, CacheManager, CACHE_MANAGER};
-use crate::device::{BlockDevice, DEVICE};
-use crate::dir::Dir;
-use crate::layout::{Fat, FsInfo, MetaData};
-use crate::utils::{u16_from_le_bytes, u32_from_le_bytes, BLOCK_SIZE};
-use alloc::boxed::Box;
+use crate::cache::{CacheClass, CacheOptions, CacheStats};
+use crate::context::FsContext;
+use crate::device::{wrap_device, BlockDevice, Device};
+use crate::dir::{Dir, File, OperationError};
+use crate::layout::{Content, Dbr, Fat, FsInfo};
+use crate::name::{label_from_str, label_to_string, ShortName};
+use crate::time::{DefaultTimeProvider, TimeProvider};
+use crate::utils::{BLOCK_SIZE, SECTOR_SIZES};
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::fmt::Debug;
 use log::error;
-use spin::{Mutex, RwLock};
+use spin::RwLock;
+
+/// 没有卷标时引导扇区中的卷标
+const NO_NAME: &ShortName = b"NO NAME    ";
+/// 卷标在引导扇区中的偏移
+const VOLUME_LABEL_OFFSET: usize = 0x47;
+
+/// 挂载选项
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    /// 目录项时间戳的来源
+    pub time: Arc<dyn TimeProvider>,
+    /// fat表、目录项、文件内容各自的缓存容量与策略
+    pub cache: CacheOptions,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            time: Arc::new(DefaultTimeProvider),
+            cache: CacheOptions::default(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Fat32 {
+    /// 引导扇区
+    dbr: Dbr,
+    fat: Arc<RwLock<Fat>>,
     root_dir: Arc<Dir>,
+    /// 当前卷独占的块设备与块缓存
+    ctx: Arc<FsContext>,
 }
 
 impl Fat32 {
-    pub fn new<T: BlockDevice<Error = ()>>(device: T) -> Result<Fat32, ()>
+    /// 没有时钟源时，所有时间戳都为1980-01-01 00:00:00
+    pub fn new<T: BlockDevice>(device: T) -> Result<Fat32, OperationError>
+    where
+        <T as BlockDevice>::Error: Debug + Send + Sync,
+    {
+        Self::with_options(device, MountOptions::default())
+    }
+    /// 使用time作为目录项时间戳的来源
+    pub fn with_time_provider<T: BlockDevice>(
+        device: T,
+        time: Arc<dyn TimeProvider>,
+    ) -> Result<Fat32, OperationError>
+    where
+        <T as BlockDevice>::Error: Debug + Send + Sync,
+    {
+        Self::with_options(
+            device,
+            MountOptions {
+                time,
+                ..Default::default()
+            },
+        )
+    }
+    /// 按照options挂载
+    pub fn with_options<T: BlockDevice>(
+        device: T,
+        options: MountOptions,
+    ) -> Result<Fat32, OperationError>
     where
-        <T as BlockDevice>::Error: Debug,
+        <T as BlockDevice>::Error: Debug + Send + Sync,
     {
+        let device = wrap_device(device);
         // 需要读取第一扇区构建原始信息
-        let mut buffer = [0; BLOCK_SIZE];
-        let _dbr = device.read(0, &mut buffer).unwrap();
-        let meta_data = MetaData {
-            bytes_per_sector: u16_from_le_bytes(&buffer[0xb..0xb + 2]),
-            sectors_per_cluster: buffer[0xd],
-            reserved_sectors: u16_from_le_bytes(&buffer[0xe..0xe + 2]),
-            number_of_fats: buffer[0x10],
-            total_sectors_32: u32_from_le_bytes(&buffer[0x20..0x20 + 4]),
-            sectors_per_fat_32: u32_from_le_bytes(&buffer[0x24..0x24 + 4]),
-            root_dir_cluster: u32_from_le_bytes(&buffer[0x2c..0x2c + 4]),
-            fs_info_sector: u16_from_le_bytes(&buffer[0x30..0x30 + 2]),
-        };
+        let buffer = read_boot_sector(&device)?;
+        let dbr = Dbr::new(&buffer[..BLOCK_SIZE]);
+        if let Err(err) = dbr.check() {
+            error!("bpb is not valid: {:?}", err);
+            return Err(OperationError::InvalidBpb(err));
+        }
+        let meta_data = dbr.meta_data();
+        // 之后的扇区都按照卷的实际扇区大小读取
+        let mut buffer = vec![0; meta_data.bytes_per_sector as usize];
         device
-            .read(meta_data.fs_info_sector as usize, &mut buffer)
-            .unwrap();
+            .lock()
+            .read(meta_data.fs_info_sector as usize, &mut buffer)?;
         let fs_info = FsInfo::new(&buffer);
         if !fs_info.is_valid() {
             error!("fs_info is not valid");
-            return Err(());
+            return Err(OperationError::InvalidFsInfo);
         }
-        unsafe {
-            CACHE_MANAGER.call_once(|| Box::new(CacheManager::new(100)));
-        }
-
-        DEVICE.call_once(|| Arc::new(Mutex::new(device)));
-        let fat = Fat::new(Arc::new(meta_data), Arc::new(fs_info));
-        fat.print_usage();
+        let ctx = Arc::new(FsContext::new(
+            device,
+            meta_data.bytes_per_sector as usize,
+            options.cache,
+            options.time,
+        ));
+        let fat = Fat::new(Arc::new(meta_data), Arc::new(fs_info), ctx.clone())?;
+        fat.print_usage()?;
+        let fat = Arc::new(RwLock::new(fat));
 
         let root_dir = Dir::new(
             meta_data.root_dir_cluster,
             (0, 0),
             Arc::new(meta_data),
-            Arc::new(RwLock::new(fat)),
-        );
+            fat.clone(),
+            ctx.clone(),
+        )?;
         Ok(Fat32 {
+            dbr,
+            fat,
             root_dir: Arc::new(root_dir),
+            ctx,
         })
     }
+    /// 引导扇区中的OEM名称
+    pub fn oem_name(&self) -> String {
+        String::from_utf8_lossy(self.dbr.oem_name())
+            .trim_end()
+            .to_string()
+    }
+    /// 卷序列号
+    pub fn volume_serial_number(&self) -> Option<u32> {
+        self.dbr.volume_serial_number()
+    }
+    /// 卷标，根目录中的卷标目录项优先，没有时使用引导扇区中的卷标
+    /// 没有设置卷标时返回None
+    pub fn label(&self) -> Result<Option<String>, OperationError> {
+        if let Some((_, label)) = self.root_dir.volume_label()? {
+            return Ok(Some(label_to_string(&label)));
+        }
+        // 引导扇区可能已经被修改，需要从缓存中读取
+        let cache = self.ctx.get_block_cache_by_id(0, CacheClass::Fat)?;
+        let label = cache.read_content(|content: &Content| Dbr::new(content.read()).volume_label());
+        Ok(label
+            .filter(|label| label != NO_NAME)
+            .map(|label| label_to_string(&label)))
+    }
+    /// 设置卷标，同时更新引导扇区以及根目录中的卷标目录项，None表示删除卷标
+    /// 卷标最多11个字符，小写字母会被转换为大写
+    pub fn set_label(&self, label: Option<&str>) -> Result<(), OperationError> {
+        let label = match label {
+            Some(label) => Some(label_from_str(label).ok_or(OperationError::InvalidName)?),
+            None => None,
+        };
+        self.root_dir.set_volume_label(label.as_ref())?;
+        // 没有扩展引导记录时引导扇区中没有卷标
+        if self.dbr.volume_label().is_none() {
+            return Ok(());
+        }
+        let sectors = core::iter::once(0).chain(self.dbr.backup_boot_sector());
+        for sector in sectors {
+            let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Fat)?;
+            cache.write(VOLUME_LABEL_OFFSET, |bpb_label: &mut ShortName| {
+                *bpb_label = label.unwrap_or(*NO_NAME);
+            });
+        }
+        Ok(())
+    }
     pub fn root_dir(&self) -> Arc<Dir> {
         self.root_dir.clone()
     }
-    pub fn sync(&self) {
-        sync();
+    /// 打开路径指向的文件，相对路径从根目录开始
+    pub fn open_path(&self, path: &str) -> Result<File, OperationError> {
+        self.root_dir.open_path(path)
+    }
+    /// 打开路径指向的目录
+    pub fn open_dir_path(&self, path: &str) -> Result<Dir, OperationError> {
+        self.root_dir.open_dir_path(path)
+    }
+    /// 在路径处创建文件，父目录必须存在
+    pub fn create_file_path(&self, path: &str) -> Result<File, OperationError> {
+        self.root_dir.create_file_path(path)
+    }
+    /// 创建路径中所有不存在的目录
+    pub fn create_dir_all(&self, path: &str) -> Result<Dir, OperationError> {
+        self.root_dir.create_dir_all(path)
+    }
+    /// 删除路径指向的文件或目录
+    pub fn remove_path(&self, path: &str) -> Result<(), OperationError> {
+        self.root_dir.remove_path(path)
+    }
+    /// 路径指向的文件或目录是否存在
+    pub fn exists(&self, path: &str) -> Result<bool, OperationError> {
+        self.root_dir.exists(path)
+    }
+    /// 将src_dir中的old移动到dst_dir中并重命名为new，目标已经存在时会被替换
+    pub fn rename(
+        &self,
+        src_dir: &Dir,
+        old: &str,
+        dst_dir: &Dir,
+        new: &str,
+    ) -> Result<(), OperationError> {
+        src_dir.rename(old, dst_dir, new)
+    }
+    /// 一类扇区的缓存统计
+    pub fn cache_stats(&self, class: CacheClass) -> CacheStats {
+        self.ctx.cache_stats(class)
+    }
+    /// 清空缓存统计，用于单独测量某一段负载
+    pub fn reset_cache_stats(&self) {
+        self.ctx.reset_cache_stats()
+    }
+    /// 将fs_info与所有缓存写回磁盘
+    pub fn sync(&self) -> Result<(), OperationError> {
+        self.fat.read().sync_fs_info()?;
+        self.ctx.sync()?;
+        Ok(())
+    }
+    /// 卸载文件系统
+    pub fn unmount(self) -> Result<(), OperationError> {
+        self.sync()
+    }
+}
+
+/// 读取引导扇区，此时还不知道扇区大小
+/// 依次尝试支持的扇区大小，只接受整扇区读写的设备在大小不匹配时会返回错误
+fn read_boot_sector(device: &Device) -> Result<Vec<u8>, OperationError> {
+    let mut error = None;
+    for size in SECTOR_SIZES {
+        let mut buffer = vec![0; size];
+        match device.lock().read(0, &mut buffer) {
+            Ok(_) => return Ok(buffer),
+            Err(err) => error = Some(err),
+        }
+    }
+    Err(error.unwrap().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只接受4096字节整扇区读写的设备
+    struct Device4Kn;
+    impl BlockDevice for Device4Kn {
+        type Error = &'static str;
+        fn read(&self, _block: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if buf.len() != 4096 {
+                return Err("unaligned read");
+            }
+            buf[510] = 0x55;
+            buf[511] = 0xaa;
+            Ok(buf.len())
+        }
+        fn write(&self, _block: usize, _buf: &[u8]) -> Result<usize, Self::Error> {
+            Err("read only")
+        }
+        fn flush(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_boot_sector() {
+        let buffer = read_boot_sector(&wrap_device(Device4Kn)).unwrap();
+        assert_eq!(buffer.len(), 4096);
+        assert_eq!(&buffer[510..512], &[0x55, 0xaa]);
     }
 }
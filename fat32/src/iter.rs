@@ -0,0 +1,201 @@
+//! 目录项迭代器
+//!
+//! 按照磁盘上的顺序逐个扇区读取目录项，不会一次性加载整个目录，
+//! 每个目录项都带有下一项的位置，可以从该位置继续遍历(readdir/getdents)
+use crate::cache::{BlockCache, CacheClass};
+use crate::context::FsContext;
+use crate::dir::OperationError;
+use crate::entry::{EntryFlags, FullLoongEntry, LongEntry, ShortEntry};
+use crate::layout::{Bpb, EntryBytes, Fat, FatEntry, MetaData};
+use crate::time::DateTime;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::RwLock;
+
+/// 目录项的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirEntryKind {
+    File,
+    Dir,
+}
+
+/// 迭代器返回的目录项
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// 长文件名，没有长目录项时为短文件名
+    pub name: String,
+    /// 8.3短文件名
+    pub short_name: String,
+    pub kind: DirEntryKind,
+    /// 文件大小，目录为0
+    pub size: u64,
+    pub attr: EntryFlags,
+    pub created: DateTime,
+    pub modified: DateTime,
+    pub accessed: DateTime,
+    pub start_cluster: u32,
+    /// 下一个目录项在目录中的字节偏移，传给`Dir::iter_from`可以继续遍历
+    pub next_offset: u64,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == DirEntryKind::Dir
+    }
+    pub fn is_file(&self) -> bool {
+        self.kind == DirEntryKind::File
+    }
+}
+
+pub struct DirIter {
+    meta: Arc<MetaData>,
+    fat: Arc<RwLock<Fat>>,
+    ctx: Arc<FsContext>,
+    /// 当前所在的簇
+    cluster: u32,
+    /// 当前位置在目录中的字节偏移
+    offset: u64,
+    /// 当前扇区的缓存
+    cache: Option<(usize, Arc<BlockCache>)>,
+    /// 遇到0x00目录项或者簇链结束
+    finished: bool,
+}
+
+impl DirIter {
+    pub(crate) fn new(
+        start_cluster: u32,
+        offset: u64,
+        meta: Arc<MetaData>,
+        fat: Arc<RwLock<Fat>>,
+        ctx: Arc<FsContext>,
+    ) -> Result<Self, OperationError> {
+        // 目录项按32字节对齐
+        let offset = offset & !31;
+        // 跳过offset之前的簇
+        let mut cluster = start_cluster;
+        let mut finished = false;
+        {
+            let fat = fat.read();
+            for _ in 0..offset / meta.bytes_per_cluster() as u64 {
+                match fat.get_entry(cluster)? {
+                    FatEntry::Cluster(next) => cluster = next,
+                    _ => {
+                        finished = true;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            meta,
+            fat,
+            ctx,
+            cluster,
+            offset,
+            cache: None,
+            finished,
+        })
+    }
+
+    /// 读取当前位置的目录项
+    fn read_entry(&mut self) -> Result<EntryBytes, OperationError> {
+        let bytes_per_cluster = self.meta.bytes_per_cluster() as u64;
+        let bytes_per_sector = self.meta.bytes_per_sector as u64;
+        let in_cluster = self.offset % bytes_per_cluster;
+        let sector =
+            self.meta.cluster_to_sector(self.cluster) + (in_cluster / bytes_per_sector) as usize;
+        // 只有进入新的扇区时才重新获取缓存
+        let cache = match &self.cache {
+            Some((id, cache)) if *id == sector => cache.clone(),
+            _ => {
+                let cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Dir)?;
+                self.cache = Some((sector, cache.clone()));
+                cache
+            }
+        };
+        let offset = (in_cluster % bytes_per_sector) as usize;
+        Ok(cache.read(offset, |entry: &EntryBytes| *entry))
+    }
+
+    /// 移动到下一个目录项，跨簇时沿着fat表找到下一个簇
+    fn advance(&mut self) -> Result<(), OperationError> {
+        self.offset += 32;
+        if self
+            .offset
+            .is_multiple_of(self.meta.bytes_per_cluster() as u64)
+        {
+            match self.fat.read().get_entry(self.cluster)? {
+                FatEntry::Cluster(next) => self.cluster = next,
+                _ => self.finished = true,
+            }
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<DirEntry>, OperationError> {
+        let mut full_long_entry = FullLoongEntry::new();
+        while !self.finished {
+            let entry = self.read_entry()?;
+            if entry[0] == 0x00 {
+                // 之后没有目录项
+                self.finished = true;
+                break;
+            }
+            self.advance()?;
+            if entry[0] == 0xE5 {
+                // 已经被删除
+                full_long_entry.clear();
+                continue;
+            }
+            let attr = EntryFlags::from_bits_truncate(entry[11]);
+            if attr.contains(EntryFlags::LONG_NAME) {
+                full_long_entry.push(LongEntry::from_buffer(&entry));
+                continue;
+            }
+            if attr.contains(EntryFlags::VOLUME_ID) {
+                // 卷标不是文件
+                full_long_entry.clear();
+                continue;
+            }
+            let short_entry = ShortEntry::from_buffer(&entry);
+            let short_name = short_entry.alias();
+            let mut name = full_long_entry.checked_filename(short_entry.check_sum());
+            if name.is_empty() {
+                name = short_entry.filename();
+            }
+            let kind = if attr.contains(EntryFlags::DIRECTORY) {
+                DirEntryKind::Dir
+            } else {
+                DirEntryKind::File
+            };
+            return Ok(Some(DirEntry {
+                name,
+                short_name,
+                kind,
+                size: short_entry.file_size() as u64,
+                attr,
+                created: short_entry.create_time(),
+                modified: short_entry.modify_time(),
+                accessed: short_entry.access_date(),
+                start_cluster: short_entry.start_cluster(),
+                next_offset: self.offset,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for DirIter {
+    type Item = Result<DirEntry, OperationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_entry() {
+            Ok(entry) => entry.map(Ok),
+            Err(err) => {
+                // 出错后不再继续
+                self.finished = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
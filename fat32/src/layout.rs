@@ -1,14 +1,16 @@
-use crate::cache::get_block_cache_by_id;
-use crate::dir::DirEntryType;
-use crate::utils::u32_from_le_bytes;
-use crate::utils::BLOCK_SIZE;
+use crate::cache::CacheClass;
+use crate::context::FsContext;
+use crate::dir::{DirEntryType, OperationError};
+use crate::name::ShortName;
+use crate::utils::{u16_from_le_bytes, u32_from_le_bytes};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::cmp::min;
 use core::fmt::Debug;
-use log::info;
+use core::ops::Range;
+use log::{error, info};
 
 pub type EntryBytes = [u8; 32];
-pub type SectorData = [u8; BLOCK_SIZE];
 
 /// 只包含部分需要的BPB参数
 #[derive(Debug, Copy, Clone, Default)]
@@ -21,19 +23,31 @@ pub struct MetaData {
     pub sectors_per_fat_32: u32,
     pub root_dir_cluster: u32,
     pub fs_info_sector: u16,
+    /// bits0-3: active FAT
+    /// bit7: 1表示只使用活动fat，0表示所有fat互为镜像
+    pub ext_flags: u16,
+    /// 备份引导扇区，为0时没有备份
+    pub backup_boot_sector: u16,
 }
 
 /// 从BiosParameterBlock需要提供的功能
 pub trait Bpb {
     /// 拿到fat1/fat2的起始扇区
     fn fat_start_sector(&self) -> usize;
+    /// 第index个fat表的起始扇区
+    fn nth_fat_start_sector(&self, index: u8) -> usize;
+    /// 关闭镜像时返回活动fat的编号
+    fn active_fat(&self) -> Option<u8>;
     /// 拿到根目录的起始扇区
     fn root_dir_start_sector(&self) -> usize;
     /// 根据数据区的簇号得到数据区的起始扇区
     fn cluster_to_sector(&self, cluster: u32) -> usize;
     fn sectors_per_fat_32(&self) -> usize;
-    fn free_cluster_count(&self) -> u32;
+    /// 数据区的簇数，簇号范围为2..cluster_count+2
+    fn cluster_count(&self) -> u32;
     fn bytes_per_cluster(&self) -> u32;
+    /// 备份fs_info扇区，位于备份引导扇区之后相同的偏移处
+    fn backup_fs_info_sector(&self) -> Option<usize>;
 }
 
 impl Bpb for MetaData {
@@ -42,6 +56,18 @@ impl Bpb for MetaData {
         self.reserved_sectors as usize
     }
     #[inline]
+    fn nth_fat_start_sector(&self, index: u8) -> usize {
+        self.fat_start_sector() + index as usize * self.sectors_per_fat_32 as usize
+    }
+    #[inline]
+    fn active_fat(&self) -> Option<u8> {
+        if self.ext_flags & 0x80 != 0 {
+            Some((self.ext_flags & 0xf) as u8)
+        } else {
+            None
+        }
+    }
+    #[inline]
     fn root_dir_start_sector(&self) -> usize {
         self.fat_start_sector() + self.number_of_fats as usize * self.sectors_per_fat_32 as usize
     }
@@ -54,31 +80,41 @@ impl Bpb for MetaData {
         self.sectors_per_fat_32 as usize
     }
     #[inline]
-    fn free_cluster_count(&self) -> u32 {
-        //计算空闲簇数
-        (self.total_sectors_32 - self.root_dir_start_sector() as u32)
+    fn cluster_count(&self) -> u32 {
+        (self.total_sectors_32).saturating_sub(self.root_dir_start_sector() as u32)
             / self.sectors_per_cluster as u32
-            - 2
     }
 
     fn bytes_per_cluster(&self) -> u32 {
         self.bytes_per_sector as u32 * self.sectors_per_cluster as u32
     }
+
+    fn backup_fs_info_sector(&self) -> Option<usize> {
+        if self.backup_boot_sector == 0 {
+            return None;
+        }
+        Some(self.backup_boot_sector as usize + self.fs_info_sector as usize)
+    }
 }
 
 /// File Allocation Table
 #[derive(Debug)]
 pub struct Fat {
     meta_data: Arc<MetaData>,
+    /// 所属文件系统的上下文
+    ctx: Arc<FsContext>,
     next_free_cluster: u32,
     total_free_cluster: u32,
 }
+/// fat32的表项只有低28位有效，高4位保留
+const FAT_ENTRY_MASK: u32 = 0x0FFFFFFF;
+
 /// 文件分配表的表项
 #[derive(Debug)]
 pub enum FatEntry {
     /// 0x00000000
     Free,
-    /// 0xFFFFFFF7
+    /// 0x0FFFFFF7
     Bad,
     /// 0x0FFFFFFF
     Eof,
@@ -86,106 +122,253 @@ pub enum FatEntry {
 }
 
 impl Fat {
-    pub fn new(meta_data: Arc<MetaData>, fs_info: Arc<FsInfo>) -> Self {
-        Self {
+    /// 簇号在第fat_index个fat表中对应的表项所在的扇区以及扇区内偏移
+    fn entry_position(&self, fat_index: u8, cluster: u32) -> (usize, usize) {
+        let bytes_per_sector = self.meta_data.bytes_per_sector as usize;
+        let fat_sector = self.meta_data.nth_fat_start_sector(fat_index)
+            + (cluster as usize * 4) / bytes_per_sector;
+        let fat_offset = (cluster as usize * 4) % bytes_per_sector;
+        (fat_sector, fat_offset)
+    }
+    /// 读取时使用的fat表
+    fn read_fat(&self) -> u8 {
+        self.meta_data.active_fat().unwrap_or(0)
+    }
+    /// 写入时需要更新的fat表
+    fn write_fats(&self) -> Range<u8> {
+        match self.meta_data.active_fat() {
+            Some(index) => index..index + 1,
+            None => 0..self.meta_data.number_of_fats,
+        }
+    }
+    /// fs_info中的值为0xFFFFFFFF或者超出范围时，需要重新计算
+    pub fn new(
+        meta_data: Arc<MetaData>,
+        fs_info: Arc<FsInfo>,
+        ctx: Arc<FsContext>,
+    ) -> Result<Self, OperationError> {
+        let cluster_count = meta_data.cluster_count();
+        let mut fat = Self {
             meta_data,
+            ctx,
             next_free_cluster: fs_info.next_free_cluster,
             total_free_cluster: fs_info.free_cluster_count,
+        };
+        if fat.total_free_cluster == 0xFFFFFFFF || fat.total_free_cluster > cluster_count {
+            fat.total_free_cluster = fat.count_free_cluster()?;
+            info!("recount free cluster: {}", fat.total_free_cluster);
+        }
+        if fat.next_free_cluster < 2 || fat.next_free_cluster >= cluster_count + 2 {
+            fat.next_free_cluster = 2;
         }
+        Ok(fat)
     }
-    pub fn empty() -> Self {
+    pub fn empty(ctx: Arc<FsContext>) -> Self {
         Self {
             meta_data: Arc::new(Default::default()),
+            ctx,
             next_free_cluster: 0,
             total_free_cluster: 0,
         }
     }
-    pub fn get_entry(&self, cluster: u32) -> FatEntry {
-        let fat_sector = self.meta_data.fat_start_sector() + (cluster as usize * 4) / BLOCK_SIZE;
-        let fat_offset = (cluster as usize * 4) % BLOCK_SIZE;
-        let sector_cache = get_block_cache_by_id(fat_sector);
-        let entry = sector_cache.read(fat_offset, |val: &u32| *val);
-        match entry {
+    pub fn get_entry(&self, cluster: u32) -> Result<FatEntry, OperationError> {
+        let (fat_sector, fat_offset) = self.entry_position(self.read_fat(), cluster);
+        let sector_cache = self
+            .ctx
+            .get_block_cache_by_id(fat_sector, CacheClass::Fat)?;
+        let entry = sector_cache.read(fat_offset, |val: &u32| *val & FAT_ENTRY_MASK);
+        Ok(match entry {
             0x00000000 => FatEntry::Free,
-            0xFFFFFFF7 => FatEntry::Bad,
+            0x0FFFFFF7 => FatEntry::Bad,
             0x0FFFFFF8..=0x0FFFFFFF => FatEntry::Eof,
             _ => FatEntry::Cluster(entry),
-        }
+        })
     }
-    pub fn set_entry(&self, cluster: u32, entry: FatEntry, dirtype: DirEntryType) {
-        let fat_sector = self.meta_data.fat_start_sector() + (cluster as usize * 4) / BLOCK_SIZE;
-        let fat_offset = (cluster as usize * 4) % BLOCK_SIZE;
-        let sector_cache = get_block_cache_by_id(fat_sector);
+    pub fn set_entry(
+        &self,
+        cluster: u32,
+        entry: FatEntry,
+        dirtype: DirEntryType,
+    ) -> Result<(), OperationError> {
         let entry = match entry {
-            FatEntry::Free => [0, 0, 0, 0],
-            FatEntry::Bad => [0xF7, 0xFF, 0xFF, 0xFF],
+            FatEntry::Free => 0,
+            FatEntry::Bad => 0x0FFFFFF7,
             FatEntry::Eof => {
                 if dirtype == DirEntryType::Dir {
-                    [0xF8, 0xFF, 0xFF, 0x0F]
+                    0x0FFFFFF8
                 } else {
-                    [0xFF, 0xFF, 0xFF, 0x0F]
+                    0x0FFFFFFF
                 }
             }
-            FatEntry::Cluster(entry) => entry.to_le_bytes(),
+            FatEntry::Cluster(entry) => entry & FAT_ENTRY_MASK,
         };
-        sector_cache.write(fat_offset, |val: &mut u32| {
-            *val = u32_from_le_bytes(&entry);
-        });
+        // 开启镜像时需要同步更新所有fat表
+        for fat_index in self.write_fats() {
+            let (fat_sector, fat_offset) = self.entry_position(fat_index, cluster);
+            let sector_cache = self
+                .ctx
+                .get_block_cache_by_id(fat_sector, CacheClass::Fat)?;
+            // 保留高4位不变
+            sector_cache.write(fat_offset, |val: &mut u32| {
+                *val = (*val & !FAT_ENTRY_MASK) | entry;
+            });
+        }
+        Ok(())
     }
 
-    pub fn alloc_cluster(&mut self) -> Option<u32> {
+    /// 分配一个空闲簇，没有空闲簇时返回[OperationError::NoEnoughSpace]
+    pub fn alloc_cluster(&mut self) -> Result<u32, OperationError> {
         if self.total_free_cluster == 0 {
-            return None;
+            return Err(OperationError::NoEnoughSpace);
         }
-        let mut cluster = self.next_free_cluster;
-        let mut entry = self.get_entry(cluster);
+        let end = self.meta_data.cluster_count() + 2;
+        let start = self.next_free_cluster.clamp(2, end - 1);
+        let mut cluster = start;
         loop {
-            match entry {
-                FatEntry::Free => {
-                    self.total_free_cluster -= 1;
-                    self.next_free_cluster = cluster + 1;
-                    return Some(cluster);
-                }
-                _ => {
-                    cluster += 1;
-                    if cluster >= self.meta_data.total_sectors_32 {
-                        cluster = 2;
-                    }
-                    entry = self.get_entry(cluster);
-                }
+            if let FatEntry::Free = self.get_entry(cluster)? {
+                self.total_free_cluster -= 1;
+                self.next_free_cluster = if cluster + 1 == end { 2 } else { cluster + 1 };
+                return Ok(cluster);
             }
+            cluster = if cluster + 1 == end { 2 } else { cluster + 1 };
+            if cluster == start {
+                // 扫描了整个fat表仍没有空闲簇，fs_info中的剩余簇数不可信
+                self.total_free_cluster = 0;
+                return Err(OperationError::NoEnoughSpace);
+            }
+        }
+    }
+
+    /// 释放从start开始的整条簇链，返回释放的簇数
+    /// 起始簇号小于2表示没有分配簇
+    pub fn free_chain(&mut self, start: u32) -> Result<usize, OperationError> {
+        if start < 2 {
+            return Ok(0);
+        }
+        let chain = self.get_cluster_chain(start)?;
+        for &cluster in chain.iter() {
+            self.set_entry(cluster, FatEntry::Free, DirEntryType::File)?;
         }
+        self.total_free_cluster += chain.len() as u32;
+        // 让下一次分配从释放的簇开始查找
+        let min_cluster = chain
+            .iter()
+            .min()
+            .copied()
+            .unwrap_or(self.next_free_cluster);
+        self.next_free_cluster = min(self.next_free_cluster, min_cluster);
+        info!("free chain from {}, {} clusters", start, chain.len());
+        Ok(chain.len())
     }
 
-    pub fn get_cluster_chain(&self, cluster: u32) -> Vec<u32> {
+    /// 将last作为簇链的最后一个簇，释放其后的所有簇
+    pub fn truncate_chain(
+        &mut self,
+        last: u32,
+        dirtype: DirEntryType,
+    ) -> Result<usize, OperationError> {
+        if last < 2 {
+            return Ok(0);
+        }
+        let next = self.get_entry(last)?;
+        self.set_entry(last, FatEntry::Eof, dirtype)?;
+        match next {
+            FatEntry::Cluster(next) => self.free_chain(next),
+            _ => Ok(0),
+        }
+    }
+
+    /// 从cluster开始的簇链
+    /// 簇链中出现空闲簇、坏簇、超出范围的簇号或者环时返回[OperationError::BadClusterChain]
+    pub fn get_cluster_chain(&self, cluster: u32) -> Result<Vec<u32>, OperationError> {
+        let end = self.meta_data.cluster_count() + 2;
         let mut chain = Vec::new();
         let mut cluster = cluster;
-        let mut entry = self.get_entry(cluster);
         loop {
-            match entry {
-                FatEntry::Eof => {
-                    chain.push(cluster);
-                    return chain;
-                }
-                FatEntry::Cluster(next) => {
-                    chain.push(cluster);
-                    entry = self.get_entry(next);
-                    cluster = next;
+            // 簇链不会比簇的总数更长，否则一定存在环
+            if !(2..end).contains(&cluster) || chain.len() >= (end - 2) as usize {
+                error!("bad cluster chain at {}", cluster);
+                return Err(OperationError::BadClusterChain);
+            }
+            chain.push(cluster);
+            match self.get_entry(cluster)? {
+                FatEntry::Eof => return Ok(chain),
+                FatEntry::Cluster(next) => cluster = next,
+                entry => {
+                    error!("bad cluster chain: {} -> {:?}", cluster, entry);
+                    return Err(OperationError::BadClusterChain);
                 }
-                _ => {
-                    panic!("bad cluster chain :{:?}", entry);
+            }
+        }
+    }
+
+    /// 簇链的长度，起始簇号小于2表示没有分配簇
+    pub fn chain_len(&self, cluster: u32) -> Result<usize, OperationError> {
+        if cluster < 2 {
+            return Ok(0);
+        }
+        Ok(self.get_cluster_chain(cluster)?.len())
+    }
+
+    /// 扫描整个fat表统计空闲簇数
+    fn count_free_cluster(&self) -> Result<u32, OperationError> {
+        let end = self.meta_data.cluster_count() + 2;
+        let entries_per_sector = self.meta_data.bytes_per_sector as u32 / 4;
+        let start = self.meta_data.nth_fat_start_sector(self.read_fat());
+        let mut count = 0;
+        for i in 0..self.meta_data.sectors_per_fat_32() {
+            let first = i as u32 * entries_per_sector;
+            if first >= end {
+                break;
+            }
+            let sector_cache = self.ctx.get_block_cache_by_id(start + i, CacheClass::Fat)?;
+            sector_cache.read_content(|content: &Content| {
+                for (index, val) in content.iter::<u32>().enumerate() {
+                    let cluster = first + index as u32;
+                    if cluster >= 2 && cluster < end && *val & FAT_ENTRY_MASK == 0 {
+                        count += 1;
+                    }
                 }
+            });
+        }
+        Ok(count)
+    }
+
+    /// 将剩余簇数和下一个空闲簇写回fs_info扇区以及它的备份
+    pub fn sync_fs_info(&self) -> Result<(), OperationError> {
+        let primary = self.meta_data.fs_info_sector as usize;
+        for sector in core::iter::once(primary).chain(self.meta_data.backup_fs_info_sector()) {
+            let sector_cache = self.ctx.get_block_cache_by_id(sector, CacheClass::Fat)?;
+            let fs_info =
+                sector_cache.read_content(|content: &Content| FsInfo::new(content.read()));
+            // 备份扇区不是合法的fs_info时不写入
+            if sector != primary && !fs_info.is_valid() {
+                continue;
+            }
+            if fs_info.free_cluster_count != self.total_free_cluster
+                || fs_info.next_free_cluster != self.next_free_cluster
+            {
+                info!(
+                    "sync fs_info {}, free: {}, next: {}",
+                    sector, self.total_free_cluster, self.next_free_cluster
+                );
+                sector_cache.write_content(|content: &mut Content| {
+                    let content = content.write();
+                    content[488..492].copy_from_slice(&self.total_free_cluster.to_le_bytes());
+                    content[492..496].copy_from_slice(&self.next_free_cluster.to_le_bytes());
+                });
             }
         }
+        Ok(())
     }
 
-    pub fn print_usage(&self) {
-        let start = self.meta_data.fat_start_sector();
+    pub fn print_usage(&self) -> Result<(), OperationError> {
+        let start = self.meta_data.nth_fat_start_sector(self.read_fat());
         let end = start + self.meta_data.sectors_per_fat_32();
         'outer: for i in start..end {
-            let sector_cache = get_block_cache_by_id(i);
+            let sector_cache = self.ctx.get_block_cache_by_id(i, CacheClass::Fat)?;
             let mut flag = false;
-            sector_cache.read(0, |content: &Content| {
+            sector_cache.read_content(|content: &Content| {
                 for val in content.iter::<u32>() {
                     if *val == 0 {
                         flag = true;
@@ -198,6 +381,7 @@ impl Fat {
                 break 'outer;
             }
         }
+        Ok(())
     }
 }
 
@@ -244,11 +428,20 @@ impl FsInfo {
 }
 
 /// 从缓存读取一个扇区的内容
+///
+/// 扇区大小由卷的`bytes_per_sector`决定
+#[repr(transparent)]
 pub struct Content {
-    data: [u8; BLOCK_SIZE],
+    data: [u8],
 }
 
 impl Content {
+    pub fn new(data: &[u8]) -> &Self {
+        unsafe { &*(data as *const [u8] as *const Content) }
+    }
+    pub fn new_mut(data: &mut [u8]) -> &mut Self {
+        unsafe { &mut *(data as *mut [u8] as *mut Content) }
+    }
     pub fn iter<T>(&self) -> impl Iterator<Item = &T> {
         self.data
             .chunks_exact(core::mem::size_of::<T>())
@@ -335,12 +528,618 @@ struct BiosParameterBlock {
     file_system_type: [u8; 8],
 }
 
-#[allow(unused)]
-struct Dbr {
+#[derive(Debug)]
+pub struct Dbr {
     /// jump code
     jump: [u8; 3],
     /// name
     oem: [u8; 8],
     /// BIOS parameter block
     bpb: BiosParameterBlock,
+    /// 0x55 0xAA
+    /// 位于扇区的第510/511字节
+    signature: u16,
+}
+
+/// 引导扇区中不符合fat32规范的字段
+#[derive(Debug, PartialEq, Eq)]
+pub enum BpbError {
+    /// 扇区末尾不是0x55AA
+    InvalidSignature(u16),
+    /// 跳转指令不是EB xx 90或E9 xx xx
+    InvalidJump([u8; 3]),
+    /// 扇区大小只能是512/1024/2048/4096
+    InvalidBytesPerSector(u16),
+    /// 每簇扇区数只能是1-128中2的幂
+    InvalidSectorsPerCluster(u8),
+    /// 保留扇区数不能为0
+    InvalidReservedSectors(u16),
+    /// 至少需要一个fat表
+    InvalidNumberOfFats(u8),
+    /// fat32的根目录项数必须为0
+    InvalidRootDirEntries(u16),
+    /// fat32的16位总扇区数必须为0
+    InvalidTotalSectors16(u16),
+    /// fat32的16位fat扇区数必须为0
+    InvalidSectorsPerFat16(u16),
+    InvalidMediaDescriptor(u8),
+    InvalidTotalSectors32(u32),
+    /// fat表的扇区数为0，或者不足以记录所有的簇
+    InvalidSectorsPerFat32(u32),
+    /// 簇数少于65525的是fat12/fat16卷
+    InvalidClusterCount(u32),
+    /// 关闭镜像时活动fat的编号超过了fat表的数量
+    InvalidExtFlags(u16),
+    /// 只支持0.0版本
+    InvalidFsVersion(u16),
+    /// 根目录簇号必须在2..簇数+2之间
+    InvalidRootDirCluster(u32),
+    /// fs_info必须位于保留区中
+    InvalidFsInfoSector(u16),
+    /// 备份引导扇区必须位于保留区中
+    InvalidBackupBootSector(u16),
+}
+
+impl BiosParameterBlock {
+    /// 从引导扇区中解析BPB，`buffer`为整个引导扇区
+    fn from_buffer(buffer: &[u8]) -> Self {
+        let mut reserved = [0u8; 12];
+        reserved.copy_from_slice(&buffer[0x34..0x34 + 12]);
+        let mut volume_label = [0u8; 11];
+        volume_label.copy_from_slice(&buffer[0x47..0x47 + 11]);
+        let mut file_system_type = [0u8; 8];
+        file_system_type.copy_from_slice(&buffer[0x52..0x52 + 8]);
+        Self {
+            bytes_per_sector: u16_from_le_bytes(&buffer[0xb..0xb + 2]),
+            sectors_per_cluster: buffer[0xd],
+            reserved_sectors: u16_from_le_bytes(&buffer[0xe..0xe + 2]),
+            number_of_fats: buffer[0x10],
+            root_dir_entries: u16_from_le_bytes(&buffer[0x11..0x11 + 2]),
+            total_sectors: u16_from_le_bytes(&buffer[0x13..0x13 + 2]),
+            media_descriptor: buffer[0x15],
+            sectors_per_fat_16: u16_from_le_bytes(&buffer[0x16..0x16 + 2]),
+            sectors_per_track: u16_from_le_bytes(&buffer[0x18..0x18 + 2]),
+            number_of_heads: u16_from_le_bytes(&buffer[0x1a..0x1a + 2]),
+            hidden_sectors: u32_from_le_bytes(&buffer[0x1c..0x1c + 4]),
+            total_sectors_32: u32_from_le_bytes(&buffer[0x20..0x20 + 4]),
+            sectors_per_fat_32: u32_from_le_bytes(&buffer[0x24..0x24 + 4]),
+            ext_flags: u16_from_le_bytes(&buffer[0x28..0x28 + 2]),
+            file_system_version: u16_from_le_bytes(&buffer[0x2a..0x2a + 2]),
+            root_dir_cluster: u32_from_le_bytes(&buffer[0x2c..0x2c + 4]),
+            file_system_info_sector: u16_from_le_bytes(&buffer[0x30..0x30 + 2]),
+            backup_boot_sector: u16_from_le_bytes(&buffer[0x32..0x32 + 2]),
+            reserved,
+            driver_number: buffer[0x40],
+            reserved1: buffer[0x41],
+            boot_signature: buffer[0x42],
+            volume_serial_number: u32_from_le_bytes(&buffer[0x43..0x43 + 4]),
+            volume_label,
+            file_system_type,
+        }
+    }
+
+    fn check(&self) -> Result<(), BpbError> {
+        if !matches!(self.bytes_per_sector, 512 | 1024 | 2048 | 4096) {
+            return Err(BpbError::InvalidBytesPerSector(self.bytes_per_sector));
+        }
+        if !self.sectors_per_cluster.is_power_of_two() {
+            return Err(BpbError::InvalidSectorsPerCluster(self.sectors_per_cluster));
+        }
+        if self.reserved_sectors == 0 {
+            return Err(BpbError::InvalidReservedSectors(self.reserved_sectors));
+        }
+        if self.number_of_fats == 0 {
+            return Err(BpbError::InvalidNumberOfFats(self.number_of_fats));
+        }
+        if self.root_dir_entries != 0 {
+            return Err(BpbError::InvalidRootDirEntries(self.root_dir_entries));
+        }
+        if self.total_sectors != 0 {
+            return Err(BpbError::InvalidTotalSectors16(self.total_sectors));
+        }
+        if !matches!(self.media_descriptor, 0xf0 | 0xf8..=0xff) {
+            return Err(BpbError::InvalidMediaDescriptor(self.media_descriptor));
+        }
+        if self.sectors_per_fat_16 != 0 {
+            return Err(BpbError::InvalidSectorsPerFat16(self.sectors_per_fat_16));
+        }
+        // 保留区和fat表之后至少还要有一个簇
+        let data_start = self.reserved_sectors as u64
+            + self.number_of_fats as u64 * self.sectors_per_fat_32 as u64;
+        if self.sectors_per_fat_32 == 0 {
+            return Err(BpbError::InvalidSectorsPerFat32(self.sectors_per_fat_32));
+        }
+        if (self.total_sectors_32 as u64) < data_start + self.sectors_per_cluster as u64 {
+            return Err(BpbError::InvalidTotalSectors32(self.total_sectors_32));
+        }
+        // fat32由簇数决定，而不是文件系统类型字符串
+        let cluster_count =
+            ((self.total_sectors_32 as u64 - data_start) / self.sectors_per_cluster as u64) as u32;
+        if cluster_count < 65525 {
+            return Err(BpbError::InvalidClusterCount(cluster_count));
+        }
+        // 每个fat表项4字节，需要覆盖簇号0..cluster_count+2
+        let fat_entries = self.sectors_per_fat_32 as u64 * self.bytes_per_sector as u64 / 4;
+        if fat_entries < cluster_count as u64 + 2 {
+            return Err(BpbError::InvalidSectorsPerFat32(self.sectors_per_fat_32));
+        }
+        // bit7为1时只使用bit0-3指定的fat
+        if self.ext_flags & 0x80 != 0 && (self.ext_flags & 0xf) as u8 >= self.number_of_fats {
+            return Err(BpbError::InvalidExtFlags(self.ext_flags));
+        }
+        if self.file_system_version != 0 {
+            return Err(BpbError::InvalidFsVersion(self.file_system_version));
+        }
+        if self.root_dir_cluster < 2 || self.root_dir_cluster >= cluster_count + 2 {
+            return Err(BpbError::InvalidRootDirCluster(self.root_dir_cluster));
+        }
+        if self.file_system_info_sector == 0
+            || self.file_system_info_sector >= self.reserved_sectors
+        {
+            return Err(BpbError::InvalidFsInfoSector(self.file_system_info_sector));
+        }
+        if self.backup_boot_sector >= self.reserved_sectors {
+            return Err(BpbError::InvalidBackupBootSector(self.backup_boot_sector));
+        }
+        Ok(())
+    }
+}
+
+impl Dbr {
+    /// 从引导扇区解析，只需要前512个字节
+    pub fn new(buffer: &[u8]) -> Self {
+        let mut jump = [0u8; 3];
+        jump.copy_from_slice(&buffer[0..3]);
+        let mut oem = [0u8; 8];
+        oem.copy_from_slice(&buffer[3..11]);
+        Self {
+            jump,
+            oem,
+            bpb: BiosParameterBlock::from_buffer(buffer),
+            signature: u16_from_le_bytes(&buffer[510..512]),
+        }
+    }
+
+    /// 检查是否是合法的fat32引导扇区
+    pub fn check(&self) -> Result<(), BpbError> {
+        if self.signature != 0xaa55 {
+            return Err(BpbError::InvalidSignature(self.signature));
+        }
+        if !matches!(self.jump, [0xeb, _, 0x90] | [0xe9, _, _]) {
+            return Err(BpbError::InvalidJump(self.jump));
+        }
+        self.bpb.check()
+    }
+
+    pub fn meta_data(&self) -> MetaData {
+        MetaData {
+            bytes_per_sector: self.bpb.bytes_per_sector,
+            sectors_per_cluster: self.bpb.sectors_per_cluster,
+            reserved_sectors: self.bpb.reserved_sectors,
+            number_of_fats: self.bpb.number_of_fats,
+            total_sectors_32: self.bpb.total_sectors_32,
+            sectors_per_fat_32: self.bpb.sectors_per_fat_32,
+            root_dir_cluster: self.bpb.root_dir_cluster,
+            fs_info_sector: self.bpb.file_system_info_sector,
+            ext_flags: self.bpb.ext_flags,
+            backup_boot_sector: self.bpb.backup_boot_sector,
+        }
+    }
+
+    /// 格式化该卷的系统名称，例如`mkfs.fat`、`MSWIN4.1`
+    pub fn oem_name(&self) -> &[u8; 8] {
+        &self.oem
+    }
+
+    /// 卷标，只有boot_signature为0x29时才有此字段
+    pub fn volume_label(&self) -> Option<ShortName> {
+        (self.bpb.boot_signature == 0x29).then_some(self.bpb.volume_label)
+    }
+
+    /// 备份引导扇区，为0时没有备份
+    pub fn backup_boot_sector(&self) -> Option<usize> {
+        (self.bpb.backup_boot_sector != 0).then_some(self.bpb.backup_boot_sector as usize)
+    }
+
+    /// 卷序列号，boot_signature为0x28或0x29时有效
+    pub fn volume_serial_number(&self) -> Option<u32> {
+        match self.bpb.boot_signature {
+            0x28 | 0x29 => Some(self.bpb.volume_serial_number),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheOptions;
+    use crate::device::{wrap_device, BlockDevice};
+    use crate::time::DefaultTimeProvider;
+    use alloc::vec;
+    use spin::Mutex;
+
+    /// 内存中的块设备
+    #[derive(Clone)]
+    struct RamDevice {
+        data: Arc<Mutex<Vec<u8>>>,
+    }
+    impl BlockDevice for RamDevice {
+        fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            let data = self.data.lock();
+            let start = block * buf.len();
+            buf.copy_from_slice(&data[start..start + buf.len()]);
+            Ok(buf.len())
+        }
+        fn write(&self, block: usize, buf: &[u8]) -> Result<usize, ()> {
+            let mut data = self.data.lock();
+            let start = block * buf.len();
+            data[start..start + buf.len()].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    /// 32个保留扇区，2个fat表各1个扇区，共64个簇
+    fn make_meta(bytes_per_sector: u16) -> MetaData {
+        MetaData {
+            bytes_per_sector,
+            sectors_per_cluster: 1,
+            reserved_sectors: 32,
+            number_of_fats: 2,
+            total_sectors_32: 32 + 2 + 64,
+            sectors_per_fat_32: 1,
+            root_dir_cluster: 2,
+            fs_info_sector: 1,
+            ext_flags: 0,
+            backup_boot_sector: 0,
+        }
+    }
+
+    fn make_fat(bytes_per_sector: u16) -> Fat {
+        let device = RamDevice {
+            data: Arc::new(Mutex::new(vec![0; bytes_per_sector as usize * 98])),
+        };
+        let ctx = Arc::new(FsContext::new(
+            wrap_device(device),
+            bytes_per_sector as usize,
+            CacheOptions::with_capacity(1),
+            Arc::new(DefaultTimeProvider),
+        ));
+        let meta = make_meta(bytes_per_sector);
+        Fat::new(Arc::new(meta), Arc::new(FsInfo::new(&[0; 512])), ctx).unwrap()
+    }
+
+    #[test]
+    fn test_entry_position() {
+        let fat = make_fat(512);
+        assert_eq!(fat.entry_position(0, 2), (32, 8));
+        assert_eq!(fat.entry_position(0, 128), (33, 0));
+        assert_eq!(fat.entry_position(1, 2), (33, 8));
+        let fat = make_fat(4096);
+        assert_eq!(fat.entry_position(0, 128), (32, 512));
+        assert_eq!(fat.entry_position(0, 1024), (33, 0));
+    }
+
+    /// mkfs.vfat生成的100M镜像的引导扇区
+    fn make_boot_sector() -> [u8; 512] {
+        let mut buffer = [0u8; 512];
+        buffer[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        buffer[3..11].copy_from_slice(b"mkfs.fat");
+        buffer[0xb..0xd].copy_from_slice(&512u16.to_le_bytes());
+        buffer[0xd] = 1;
+        buffer[0xe..0x10].copy_from_slice(&32u16.to_le_bytes());
+        buffer[0x10] = 2;
+        buffer[0x15] = 0xf8;
+        buffer[0x20..0x24].copy_from_slice(&204800u32.to_le_bytes());
+        buffer[0x24..0x28].copy_from_slice(&1600u32.to_le_bytes());
+        buffer[0x2c..0x30].copy_from_slice(&2u32.to_le_bytes());
+        buffer[0x30..0x32].copy_from_slice(&1u16.to_le_bytes());
+        buffer[0x32..0x34].copy_from_slice(&6u16.to_le_bytes());
+        buffer[0x42] = 0x29;
+        buffer[0x43..0x47].copy_from_slice(&0x12345678u32.to_le_bytes());
+        buffer[0x47..0x52].copy_from_slice(b"NO NAME    ");
+        buffer[0x52..0x5a].copy_from_slice(b"FAT32   ");
+        buffer[510] = 0x55;
+        buffer[511] = 0xaa;
+        buffer
+    }
+
+    #[test]
+    fn test_dbr_new() {
+        let dbr = Dbr::new(&make_boot_sector());
+        assert_eq!(dbr.check(), Ok(()));
+        assert_eq!(dbr.volume_serial_number(), Some(0x12345678));
+        assert_eq!(dbr.oem_name(), b"mkfs.fat");
+        let meta = dbr.meta_data();
+        assert_eq!(meta.bytes_per_sector, 512);
+        assert_eq!(meta.reserved_sectors, 32);
+        assert_eq!(meta.number_of_fats, 2);
+        assert_eq!(meta.sectors_per_fat_32, 1600);
+        assert_eq!(meta.root_dir_cluster, 2);
+        assert_eq!(meta.fs_info_sector, 1);
+    }
+
+    #[test]
+    fn test_dbr_check() {
+        let check = |f: fn(&mut [u8; 512])| {
+            let mut buffer = make_boot_sector();
+            f(&mut buffer);
+            Dbr::new(&buffer).check()
+        };
+        assert_eq!(
+            check(|b| b[511] = 0),
+            Err(BpbError::InvalidSignature(0x0055))
+        );
+        assert_eq!(
+            check(|b| b[0] = 0),
+            Err(BpbError::InvalidJump([0, 0x58, 0x90]))
+        );
+        assert_eq!(
+            check(|b| b[0xb..0xd].copy_from_slice(&768u16.to_le_bytes())),
+            Err(BpbError::InvalidBytesPerSector(768))
+        );
+        assert_eq!(
+            check(|b| b[0xd] = 0),
+            Err(BpbError::InvalidSectorsPerCluster(0))
+        );
+        assert_eq!(
+            check(|b| b[0x11] = 1),
+            Err(BpbError::InvalidRootDirEntries(1))
+        );
+        assert_eq!(
+            check(|b| b[0x15] = 0x10),
+            Err(BpbError::InvalidMediaDescriptor(0x10))
+        );
+        assert_eq!(
+            check(|b| b[0x28] = 0x82),
+            Err(BpbError::InvalidExtFlags(0x82))
+        );
+        assert_eq!(check(|b| b[0x28] = 0x81), Ok(()));
+        assert_eq!(check(|b| b[0x2a] = 1), Err(BpbError::InvalidFsVersion(1)));
+        // 数据区有201568个簇
+        assert_eq!(
+            check(|b| b[0xd] = 4),
+            Err(BpbError::InvalidClusterCount(50392))
+        );
+        assert_eq!(
+            check(|b| b[0x20..0x24].copy_from_slice(&68756u32.to_le_bytes())),
+            Err(BpbError::InvalidClusterCount(65524))
+        );
+        assert_eq!(
+            check(|b| b[0x20..0x24].copy_from_slice(&68757u32.to_le_bytes())),
+            Ok(())
+        );
+        assert_eq!(
+            check(|b| b[0x24..0x28].copy_from_slice(&1500u32.to_le_bytes())),
+            Err(BpbError::InvalidSectorsPerFat32(1500))
+        );
+        assert_eq!(
+            check(|b| b[0x24..0x28].copy_from_slice(&0u32.to_le_bytes())),
+            Err(BpbError::InvalidSectorsPerFat32(0))
+        );
+        assert_eq!(
+            check(|b| b[0x2c..0x30].copy_from_slice(&1u32.to_le_bytes())),
+            Err(BpbError::InvalidRootDirCluster(1))
+        );
+        assert_eq!(
+            check(|b| b[0x2c..0x30].copy_from_slice(&201570u32.to_le_bytes())),
+            Err(BpbError::InvalidRootDirCluster(201570))
+        );
+        assert_eq!(
+            check(|b| b[0x2c..0x30].copy_from_slice(&201569u32.to_le_bytes())),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_fs_info_recount_and_sync() {
+        let meta = MetaData {
+            backup_boot_sector: 6,
+            ..make_meta(512)
+        };
+        let mut image = vec![0u8; 512 * 98];
+        // 簇2是根目录，簇3-4是一个文件
+        let fat = [0x0FFFFFF8u32, 0x0FFFFFFF, 0x0FFFFFF8, 4, 0x0FFFFFFF];
+        for (i, val) in fat.iter().enumerate() {
+            image[32 * 512 + i * 4..32 * 512 + i * 4 + 4].copy_from_slice(&val.to_le_bytes());
+        }
+        image[512..516].copy_from_slice(&0x41615252u32.to_le_bytes());
+        image[512 + 484..512 + 488].copy_from_slice(&0x61417272u32.to_le_bytes());
+        image[512 + 488..512 + 496].fill(0xff);
+        image[512 + 508..512 + 512].copy_from_slice(&0xaa550000u32.to_le_bytes());
+        image.copy_within(512..1024, 7 * 512);
+        let device = RamDevice {
+            data: Arc::new(Mutex::new(image)),
+        };
+        let ctx = Arc::new(FsContext::new(
+            wrap_device(device.clone()),
+            512,
+            CacheOptions::with_capacity(4),
+            Arc::new(DefaultTimeProvider),
+        ));
+        let fs_info = FsInfo::new(&device.data.lock()[512..1024]);
+        let mut fat = Fat::new(Arc::new(meta), Arc::new(fs_info), ctx.clone()).unwrap();
+        assert_eq!(fat.total_free_cluster, 61);
+        assert_eq!(fat.next_free_cluster, 2);
+        assert_eq!(fat.alloc_cluster().unwrap(), 5);
+        fat.sync_fs_info().unwrap();
+        ctx.sync().unwrap();
+        // 备份的fs_info同样被更新
+        for sector in [1, 7] {
+            let fs_info = FsInfo::new(&device.data.lock()[sector * 512..(sector + 1) * 512]);
+            assert!(fs_info.is_valid());
+            assert_eq!(fs_info.free_cluster_count, 60);
+            assert_eq!(fs_info.next_free_cluster, 6);
+        }
+    }
+
+    fn fat_entry_in_image(device: &RamDevice, fat_index: usize, cluster: usize) -> u32 {
+        let offset = (32 + fat_index) * 512 + cluster * 4;
+        u32_from_le_bytes(&device.data.lock()[offset..offset + 4])
+    }
+
+    #[test]
+    fn test_fat_entry_reserved_bits() {
+        let device = RamDevice {
+            data: Arc::new(Mutex::new(vec![0; 512 * 98])),
+        };
+        let set_raw = |fat_index: usize, cluster: usize, val: u32| {
+            let offset = (32 + fat_index) * 512 + cluster * 4;
+            device.data.lock()[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+        };
+        for fat_index in 0..2 {
+            set_raw(fat_index, 3, 0xA0000005);
+            set_raw(fat_index, 4, 0xF0000000);
+            set_raw(fat_index, 5, 0xFFFFFFF7);
+            set_raw(fat_index, 6, 0xFFFFFFFF);
+        }
+        let ctx = Arc::new(FsContext::new(
+            wrap_device(device.clone()),
+            512,
+            CacheOptions::with_capacity(4),
+            Arc::new(DefaultTimeProvider),
+        ));
+        let fs_info = FsInfo::new(&[0; 512]);
+        let fat = Fat::new(Arc::new(make_meta(512)), Arc::new(fs_info), ctx.clone()).unwrap();
+        // 读取时忽略高4位
+        assert!(matches!(fat.get_entry(3).unwrap(), FatEntry::Cluster(5)));
+        assert!(matches!(fat.get_entry(4).unwrap(), FatEntry::Free));
+        assert!(matches!(fat.get_entry(5).unwrap(), FatEntry::Bad));
+        assert!(matches!(fat.get_entry(6).unwrap(), FatEntry::Eof));
+        // 写入时保留每个fat表中原来的高4位
+        fat.set_entry(3, FatEntry::Cluster(9), DirEntryType::File)
+            .unwrap();
+        fat.set_entry(4, FatEntry::Eof, DirEntryType::File).unwrap();
+        fat.set_entry(5, FatEntry::Free, DirEntryType::File)
+            .unwrap();
+        ctx.sync().unwrap();
+        for fat_index in 0..2 {
+            assert_eq!(fat_entry_in_image(&device, fat_index, 3), 0xA0000009);
+            assert_eq!(fat_entry_in_image(&device, fat_index, 4), 0xFFFFFFFF);
+            assert_eq!(fat_entry_in_image(&device, fat_index, 5), 0xF0000000);
+        }
+    }
+
+    #[test]
+    fn test_fat_mirror() {
+        for (ext_flags, fats) in [(0x0, [true, true]), (0x81, [false, true])] {
+            let device = RamDevice {
+                data: Arc::new(Mutex::new(vec![0; 512 * 98])),
+            };
+            let ctx = Arc::new(FsContext::new(
+                wrap_device(device.clone()),
+                512,
+                CacheOptions::with_capacity(4),
+                Arc::new(DefaultTimeProvider),
+            ));
+            let meta = MetaData {
+                ext_flags,
+                ..make_meta(512)
+            };
+            let fs_info = FsInfo::new(&[0; 512]);
+            let fat = Fat::new(Arc::new(meta), Arc::new(fs_info), ctx.clone()).unwrap();
+            fat.set_entry(3, FatEntry::Cluster(7), DirEntryType::File)
+                .unwrap();
+            assert!(matches!(fat.get_entry(3).unwrap(), FatEntry::Cluster(7)));
+            ctx.sync().unwrap();
+            for (index, updated) in fats.iter().enumerate() {
+                let expect = if *updated { 7 } else { 0 };
+                assert_eq!(fat_entry_in_image(&device, index, 3), expect);
+            }
+        }
+    }
+
+    #[test]
+    fn test_free_chain() {
+        let mut fat = make_fat(512);
+        fat.total_free_cluster = 64;
+        fat.next_free_cluster = 2;
+        let mut chain = Vec::new();
+        for _ in 0..4 {
+            chain.push(fat.alloc_cluster().unwrap());
+        }
+        for pair in chain.windows(2) {
+            fat.set_entry(pair[0], FatEntry::Cluster(pair[1]), DirEntryType::File)
+                .unwrap();
+        }
+        fat.set_entry(chain[3], FatEntry::Eof, DirEntryType::File)
+            .unwrap();
+        assert_eq!(fat.total_free_cluster, 60);
+        assert_eq!(fat.next_free_cluster, 6);
+        assert_eq!(fat.truncate_chain(chain[1], DirEntryType::File).unwrap(), 2);
+        assert_eq!(fat.get_cluster_chain(chain[0]).unwrap(), &chain[0..2]);
+        assert_eq!(fat.total_free_cluster, 62);
+        assert_eq!(fat.next_free_cluster, 4);
+        assert_eq!(fat.free_chain(chain[0]).unwrap(), 2);
+        assert_eq!(fat.total_free_cluster, 64);
+        assert_eq!(fat.next_free_cluster, 2);
+        assert!(matches!(fat.get_entry(chain[0]).unwrap(), FatEntry::Free));
+        // 簇号为0的空文件没有簇链
+        fat.set_entry(0, FatEntry::Eof, DirEntryType::Dir).unwrap();
+        assert_eq!(fat.free_chain(0).unwrap(), 0);
+        assert_eq!(fat.truncate_chain(0, DirEntryType::File).unwrap(), 0);
+        assert!(matches!(fat.get_entry(0).unwrap(), FatEntry::Eof));
+        assert_eq!(fat.total_free_cluster, 64);
+        assert_eq!(fat.next_free_cluster, 2);
+    }
+
+    #[test]
+    fn test_bad_cluster_chain() {
+        let mut fat = make_fat(512);
+        fat.total_free_cluster = 64;
+        let bad = |fat: &mut Fat, start| {
+            matches!(fat.free_chain(start), Err(OperationError::BadClusterChain))
+        };
+        // 指向空闲簇
+        fat.set_entry(2, FatEntry::Cluster(3), DirEntryType::File)
+            .unwrap();
+        assert!(bad(&mut fat, 2));
+        // 指向坏簇
+        fat.set_entry(3, FatEntry::Bad, DirEntryType::File).unwrap();
+        assert!(bad(&mut fat, 2));
+        // 簇号超出范围
+        fat.set_entry(3, FatEntry::Cluster(66), DirEntryType::File)
+            .unwrap();
+        assert!(bad(&mut fat, 2));
+        fat.set_entry(3, FatEntry::Cluster(1), DirEntryType::File)
+            .unwrap();
+        assert!(bad(&mut fat, 2));
+        // 簇链中存在环
+        fat.set_entry(3, FatEntry::Cluster(2), DirEntryType::File)
+            .unwrap();
+        assert!(bad(&mut fat, 2));
+        assert!(matches!(
+            fat.chain_len(3),
+            Err(OperationError::BadClusterChain)
+        ));
+        // 出错时不会释放任何簇
+        assert_eq!(fat.total_free_cluster, 64);
+    }
+
+    #[test]
+    fn test_alloc_cluster_bounded() {
+        let mut fat = make_fat(512);
+        // 分配最后一个簇后从头开始查找
+        fat.total_free_cluster = 64;
+        fat.next_free_cluster = 65;
+        assert_eq!(fat.alloc_cluster().unwrap(), 65);
+        assert_eq!(fat.next_free_cluster, 2);
+        // fs_info记录的剩余簇数与fat表不一致
+        for cluster in 2..65 {
+            fat.set_entry(cluster, FatEntry::Eof, DirEntryType::File)
+                .unwrap();
+        }
+        fat.set_entry(65, FatEntry::Eof, DirEntryType::File)
+            .unwrap();
+        fat.total_free_cluster = 10;
+        fat.next_free_cluster = 1000;
+        assert!(matches!(
+            fat.alloc_cluster(),
+            Err(OperationError::NoEnoughSpace)
+        ));
+        assert_eq!(fat.total_free_cluster, 0);
+    }
 }
@@ -3,15 +3,29 @@
 #![feature(error_in_core)]
 #![no_std]
 mod cache;
+mod context;
 mod device;
 mod dir;
 mod entry;
 mod fat32;
+mod iter;
 mod layout;
+mod name;
+mod path;
+mod time;
 mod utils;
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
-pub use crate::fat32::Fat32;
-pub use device::BlockDevice;
-pub use dir::{Dir, File, OperationError};
+pub use crate::fat32::{Fat32, MountOptions};
+pub use cache::{CacheClass, CacheConfig, CacheOptions, CachePolicy, CacheStats};
+pub use device::{BlockDevice, DeviceError};
+pub use dir::{Dir, File, Metadata, OperationError, MAX_FILE_SIZE};
+pub use entry::EntryFlags;
+pub use iter::{DirEntry, DirEntryKind, DirIter};
+pub use layout::BpbError;
+#[cfg(feature = "std")]
+pub use time::StdTimeProvider;
+pub use time::{DateTime, DefaultTimeProvider, TimeProvider};
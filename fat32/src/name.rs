@@ -0,0 +1,376 @@
+//! 8.3短文件名的生成
+//!
+//! 按照微软fat规范中的basis-name算法由长文件名生成短文件名，
+//! 字符使用OEM代码页437编码，无法表示的字符替换为`_`。
+//! 转换有损或者与目录中已有的短文件名冲突时添加`~n`数字尾，
+//! 相似的名称过多时使用长文件名的哈希值代替部分基本名。
+//! 能够直接用8.3表示的名称不需要长目录项，全小写的基本名或扩展名通过
+//! Windows NT的大小写标志保存
+use alloc::format;
+use alloc::string::String;
+
+/// 目录项中的11字节短文件名，基本名8字节，扩展名3字节，不足的部分用空格填充
+pub type ShortName = [u8; 11];
+
+/// .目录项的短文件名
+pub const DOT_NAME: ShortName = *b".          ";
+/// ..目录项的短文件名
+pub const DOTDOT_NAME: ShortName = *b"..         ";
+
+/// 基本名全部小写
+pub const CASE_LOWER_BASE: u8 = 0x08;
+/// 扩展名全部小写
+pub const CASE_LOWER_EXT: u8 = 0x10;
+
+/// 代码页437中0x80-0xFF对应的字符
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// 长文件名最多255个UTF-16码元
+pub const MAX_NAME_LEN: usize = 255;
+
+/// 长文件名中不合法的字符
+const INVALID_LONG_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// 检查长文件名，名称不能为空，不能包含控制字符以及`"*/:<>?\|`
+/// 与Windows相同，不能以`.`或空格结尾，因此`.`和`..`也不合法
+pub fn is_valid_long_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.ends_with(['.', ' '])
+        && !name
+            .chars()
+            .any(|c| (c as u32) < 0x20 || INVALID_LONG_CHARS.contains(&c))
+}
+
+/// 长文件名中合法，但短文件名中不合法的字符
+const INVALID_SHORT_CHARS: &[u8] = b"\"*+,/:;<=>?[\\]|";
+
+/// OEM字节转换为字符
+pub fn oem_to_char(byte: u8) -> char {
+    if byte < 0x80 {
+        byte as char
+    } else {
+        CP437_HIGH[(byte - 0x80) as usize]
+    }
+}
+
+/// 字符转换为大写的OEM字节，无法表示时返回None
+fn char_to_oem(c: char) -> Option<u8> {
+    // 只取单个字符的大写形式，例如ß的大写是SS，此时保留原字符
+    let mut upper = c.to_uppercase();
+    let c = match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    };
+    if c.is_ascii() {
+        let byte = c as u8;
+        return (byte >= 0x20 && byte != 0x7F && !INVALID_SHORT_CHARS.contains(&byte))
+            .then_some(byte);
+    }
+    CP437_HIGH
+        .iter()
+        .position(|&x| x == c)
+        .map(|i| 0x80 + i as u8)
+}
+
+/// 将字符依次写入dst，返回是否有损
+/// 空格和.被丢弃，无法表示的字符替换为_，超出dst长度的部分被截断
+fn fill_oem(src: &str, dst: &mut [u8]) -> bool {
+    let mut lossy = false;
+    let mut len = 0;
+    for c in src.chars() {
+        if c == ' ' || c == '.' {
+            lossy = true;
+            continue;
+        }
+        if len == dst.len() {
+            return true;
+        }
+        dst[len] = char_to_oem(c).unwrap_or_else(|| {
+            lossy = true;
+            b'_'
+        });
+        len += 1;
+    }
+    lossy
+}
+
+/// 由长文件名生成基本名，返回基本名以及转换是否有损
+pub fn basis_name(name: &str) -> (ShortName, bool) {
+    let mut basis = [b' '; 11];
+    // 去掉开头的.
+    let trimmed = name.trim_start_matches('.');
+    let mut lossy = trimmed.len() != name.len();
+    // 最后一个.之后的部分是扩展名
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(index) => (&trimmed[..index], &trimmed[index + 1..]),
+        None => (trimmed, ""),
+    };
+    lossy |= fill_oem(base, &mut basis[0..8]);
+    lossy |= fill_oem(ext, &mut basis[8..11]);
+    if basis[0] == b' ' {
+        // 基本名为空，必须添加数字尾
+        lossy = true;
+    }
+    // 0xE5表示目录项已被删除，需要用0x05代替
+    if basis[0] == 0xE5 {
+        basis[0] = 0x05;
+    }
+    (basis, lossy)
+}
+
+/// 按照大小写标志将短文件名转换为字符串
+/// 去掉末尾的空格，0x05表示第一个字符是0xE5
+pub fn short_name_to_string(name: &ShortName, case: u8) -> String {
+    let decode = |bytes: &[u8], lower: bool, ans: &mut String| {
+        let len = bytes
+            .iter()
+            .rposition(|&b| b != 0x20 && b != 0x00)
+            .map_or(0, |i| i + 1);
+        for (i, &b) in bytes[..len].iter().enumerate() {
+            let c = oem_to_char(if i == 0 && b == 0x05 { 0xE5 } else { b });
+            if lower {
+                ans.extend(c.to_lowercase());
+            } else {
+                ans.push(c);
+            }
+        }
+    };
+    let mut ans = String::new();
+    decode(&name[0..8], case & CASE_LOWER_BASE != 0, &mut ans);
+    let mut ext = String::new();
+    decode(&name[8..11], case & CASE_LOWER_EXT != 0, &mut ext);
+    if !ext.is_empty() {
+        ans.push('.');
+        ans.push_str(&ext);
+    }
+    ans
+}
+
+/// 卷标转换为字符串，去掉末尾的空格
+pub fn label_to_string(label: &ShortName) -> String {
+    let len = label
+        .iter()
+        .rposition(|&b| b != 0x20 && b != 0x00)
+        .map_or(0, |i| i + 1);
+    label[..len].iter().map(|&b| oem_to_char(b)).collect()
+}
+
+/// 字符串转换为卷标，最多11个字符，可以包含空格但不能包含.以及短文件名中不合法的字符
+/// 小写字母会被转换为大写
+pub fn label_from_str(label: &str) -> Option<ShortName> {
+    let label = label.trim_end_matches(' ');
+    let mut ans = [b' '; 11];
+    if label.is_empty() || label.chars().count() > ans.len() {
+        return None;
+    }
+    for (c, byte) in label.chars().zip(ans.iter_mut()) {
+        *byte = char_to_oem(c).filter(|&b| b != b'.')?;
+    }
+    if ans[0] == 0xE5 {
+        ans[0] = 0x05;
+    }
+    Some(ans)
+}
+
+/// 大小写标志，基本名和扩展名都只能是全大写或全小写
+fn case_flag(part: &str, flag: u8) -> u8 {
+    if part.chars().any(|c| c.is_lowercase()) {
+        flag
+    } else {
+        0
+    }
+}
+
+/// 名称可以不使用长目录项保存时，返回短文件名以及大小写标志
+pub fn exact_short_name(name: &str) -> Option<(ShortName, u8)> {
+    let (basis, lossy) = basis_name(name);
+    if lossy {
+        return None;
+    }
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let case = case_flag(base, CASE_LOWER_BASE) | case_flag(ext, CASE_LOWER_EXT);
+    // 大小写混合或者名称无法还原时需要长目录项
+    (short_name_to_string(&basis, case) == name).then_some((basis, case))
+}
+
+/// 基本名的长度
+fn base_len(name: &ShortName) -> usize {
+    name[0..8].iter().position(|&b| b == b' ').unwrap_or(8)
+}
+
+/// 在基本名后添加~n
+fn numeric_tail(basis: &ShortName, n: u32) -> ShortName {
+    let tail = format!("~{n}");
+    let keep = base_len(basis).min(8 - tail.len());
+    let mut name = *basis;
+    name[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+    name[keep + tail.len()..8].fill(b' ');
+    name
+}
+
+/// 保留基本名的前两个字符，之后是长文件名的16位哈希值
+fn hash_basis(basis: &ShortName, name: &str) -> ShortName {
+    let hash = name.encode_utf16().fold(0u16, |sum, unit| {
+        ((sum & 1) << 15 | sum >> 1).wrapping_add(unit)
+    });
+    let keep = base_len(basis).min(2);
+    let mut hashed = *basis;
+    let hex = format!("{hash:04X}");
+    hashed[keep..keep + 4].copy_from_slice(hex.as_bytes());
+    hashed[keep + 4..8].fill(b' ');
+    hashed
+}
+
+/// 生成目录中唯一的短文件名，exists判断短文件名是否已经被占用
+/// 先尝试基本名，再尝试~1到~4，最后使用哈希值加上~1到~999999
+pub fn generate_short_name(name: &str, exists: impl Fn(&ShortName) -> bool) -> Option<ShortName> {
+    let (basis, lossy) = basis_name(name);
+    if !lossy && !exists(&basis) {
+        return Some(basis);
+    }
+    if let Some(name) = (1..=4)
+        .map(|n| numeric_tail(&basis, n))
+        .find(|x| !exists(x))
+    {
+        return Some(name);
+    }
+    // 目录中相似的名称太多
+    let hashed = hash_basis(&basis, name);
+    (1..=999999)
+        .map(|n| numeric_tail(&hashed, n))
+        .find(|x| !exists(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeSet;
+
+    fn short(name: &str) -> ShortName {
+        let mut ans = [b' '; 11];
+        let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+        ans[..base.len()].copy_from_slice(base.as_bytes());
+        ans[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        ans
+    }
+
+    #[test]
+    fn test_basis_name() {
+        assert_eq!(basis_name("readme.txt"), (short("README.TXT"), false));
+        assert_eq!(basis_name("Makefile"), (short("MAKEFILE"), false));
+        assert_eq!(basis_name("hello1234.txt"), (short("HELLO123.TXT"), true));
+        assert_eq!(basis_name("a.long_ext"), (short("A.LON"), true));
+        assert_eq!(basis_name(".bashrc"), (short("BASHRC"), true));
+        assert_eq!(basis_name("my file.a.b"), (short("MYFILEA.B"), true));
+        assert_eq!(basis_name("a+b[1].c"), (short("A_B_1_.C"), true));
+        assert_eq!(
+            basis_name("über.txt"),
+            (
+                [0x9A, b'B', b'E', b'R', b' ', b' ', b' ', b' ', b'T', b'X', b'T'],
+                false
+            )
+        );
+        assert!(basis_name("文件.txt").1);
+        assert!(basis_name("...").1);
+    }
+
+    #[test]
+    fn test_valid_long_name() {
+        assert!(is_valid_long_name("Program Files"));
+        assert!(is_valid_long_name("a+b[1];c.txt"));
+        assert!(is_valid_long_name("文件.txt"));
+        assert!(!is_valid_long_name(""));
+        assert!(is_valid_long_name(".hidden"));
+        assert!(is_valid_long_name("a. b"));
+        for name in [
+            "a\"b", "a*", "a/b", "c:", "<a>", "a?", "a\\b", "a|b", "a\tb", ".", "..", "...", "a.",
+            "a ", " ", "a. ",
+        ] {
+            assert!(!is_valid_long_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label_from_str("my disk"), Some(*b"MY DISK    "));
+        assert_eq!(label_from_str("ABCDEFGHIJK"), Some(*b"ABCDEFGHIJK"));
+        assert_eq!(label_from_str("ABCDEFGHIJKL"), None);
+        assert_eq!(label_from_str("a.b"), None);
+        assert_eq!(label_from_str("a*b"), None);
+        assert_eq!(label_from_str("   "), None);
+        assert_eq!(label_to_string(b"MY DISK    "), "MY DISK");
+    }
+
+    #[test]
+    fn test_exact_short_name() {
+        assert_eq!(
+            exact_short_name("readme.txt"),
+            Some((short("README.TXT"), CASE_LOWER_BASE | CASE_LOWER_EXT))
+        );
+        assert_eq!(exact_short_name("Makefile"), None);
+        assert_eq!(exact_short_name("MAKEFILE"), Some((short("MAKEFILE"), 0)));
+        assert_eq!(
+            exact_short_name("README.md"),
+            Some((short("README.MD"), CASE_LOWER_EXT))
+        );
+        assert_eq!(exact_short_name("f1"), Some((short("F1"), CASE_LOWER_BASE)));
+        assert_eq!(exact_short_name("ReadMe.txt"), None);
+        assert_eq!(exact_short_name("abc."), None);
+        assert_eq!(exact_short_name("hello1234.txt"), None);
+        assert_eq!(
+            short_name_to_string(&short("README.TXT"), CASE_LOWER_EXT),
+            "README.txt"
+        );
+    }
+
+    #[test]
+    fn test_numeric_tail() {
+        let exists = |_: &ShortName| false;
+        assert_eq!(
+            generate_short_name("hello1234.txt", exists),
+            Some(short("HELLO1~1.TXT"))
+        );
+        assert_eq!(
+            generate_short_name("Program Files", exists),
+            Some(short("PROGRA~1"))
+        );
+        assert_eq!(
+            generate_short_name("readme.txt", exists),
+            Some(short("README.TXT"))
+        );
+        assert_eq!(numeric_tail(&short("AB.C"), 123456), short("A~123456.C"));
+        assert_eq!(numeric_tail(&short("HELLO123"), 999999), short("H~999999"));
+    }
+
+    #[test]
+    fn test_unique_short_name() {
+        let mut used = BTreeSet::new();
+        // 没有损失的名称冲突时也需要数字尾
+        used.insert(short("README.TXT"));
+        assert_eq!(
+            generate_short_name("readme.txt", |x| used.contains(x)),
+            Some(short("README~1.TXT"))
+        );
+        let mut names = BTreeSet::new();
+        for i in 0..20 {
+            let name =
+                generate_short_name(&format!("long_file_name_{i}.txt"), |x| names.contains(x))
+                    .unwrap();
+            assert!(names.insert(name));
+        }
+        assert!(names.contains(&short("LONG_F~4.TXT")));
+        // 第5个开始使用哈希
+        assert!(!names.contains(&short("LONG_F~5.TXT")));
+        let hashed = names.iter().filter(|x| !x.starts_with(b"LONG_F~"));
+        assert_eq!(hashed.count(), 16);
+    }
+}
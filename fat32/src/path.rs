@@ -0,0 +1,197 @@
+//! 基于路径的文件操作
+//!
+//! 路径使用`/`分隔，以`/`开头的是绝对路径，否则相对于当前目录。
+//! 重复或结尾的`/`以及`.`会被忽略，`..`回到上一级目录，根目录的`..`仍是根目录，
+//! 每一部分都不区分大小写
+use crate::dir::{Dir, File, OperationError};
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use fat32_trait::DirectoryLike;
+
+/// 拆分路径，返回是否是绝对路径以及各个部分
+fn split_path(path: &str) -> (bool, Vec<&str>) {
+    let components = path
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+    (path.starts_with('/'), components)
+}
+
+fn path_error(component: &str, error: OperationError) -> OperationError {
+    OperationError::Path {
+        component: component.to_string(),
+        error: Box::new(error),
+    }
+}
+
+/// 最后一部分必须是普通的名称
+fn check_name(name: &str) -> Result<(), OperationError> {
+    if name == ".." {
+        return Err(path_error(name, OperationError::InvalidDirName));
+    }
+    Ok(())
+}
+
+impl Dir {
+    /// 上一级目录，根目录的上一级是它自己
+    pub(crate) fn parent(&self) -> Result<Dir, OperationError> {
+        if self.is_root() {
+            return Ok(self.clone());
+        }
+        let (_, parent) = self
+            .find_dir("..")
+            .ok_or_else(|| path_error("..", OperationError::DirNotFound))?;
+        parent.reload()?;
+        Ok(parent)
+    }
+
+    /// 沿着..找到根目录
+    fn root(&self) -> Result<Dir, OperationError> {
+        let mut dir = self.clone();
+        while !dir.is_root() {
+            dir = dir.parent()?;
+        }
+        Ok(dir)
+    }
+
+    /// 从起始目录开始的目录栈，`..`优先回到栈中已经打开的目录，
+    /// 这样对上一级目录的修改能反映到调用者持有的Dir上。
+    /// 起始目录会重新加载，其它Dir经由别的路径做的修改也能看到
+    fn start_stack(&self, absolute: bool) -> Result<Vec<Dir>, OperationError> {
+        let start = if absolute { self.root()? } else { self.clone() };
+        start.reload()?;
+        Ok(vec![start])
+    }
+
+    /// 处理`..`
+    fn pop_stack(stack: &mut Vec<Dir>) -> Result<(), OperationError> {
+        if stack.len() > 1 {
+            stack.pop();
+        } else {
+            stack[0] = stack[0].parent()?;
+        }
+        Ok(())
+    }
+
+    /// 依次进入路径中的每一个目录
+    fn walk(&self, absolute: bool, components: &[&str]) -> Result<Dir, OperationError> {
+        let mut stack = self.start_stack(absolute)?;
+        for &component in components {
+            if component == ".." {
+                Self::pop_stack(&mut stack)?;
+                continue;
+            }
+            let (_, next) = stack
+                .last()
+                .unwrap()
+                .find_dir(component)
+                .ok_or_else(|| path_error(component, OperationError::DirNotFound))?;
+            next.reload()?;
+            stack.push(next);
+        }
+        Ok(stack.pop().unwrap())
+    }
+
+    /// 找到路径的父目录以及最后一部分
+    /// 路径指向当前目录或根目录时最后一部分为None
+    fn walk_parent<'a>(&self, path: &'a str) -> Result<(Dir, Option<&'a str>), OperationError> {
+        let (absolute, mut components) = split_path(path);
+        match components.pop() {
+            Some(name) if name != ".." => Ok((self.walk(absolute, &components)?, Some(name))),
+            Some(name) => {
+                components.push(name);
+                Ok((self.walk(absolute, &components)?, None))
+            }
+            None => Ok((self.walk(absolute, &components)?, None)),
+        }
+    }
+
+    /// 打开路径指向的目录
+    pub fn open_dir_path(&self, path: &str) -> Result<Dir, OperationError> {
+        let (absolute, components) = split_path(path);
+        self.walk(absolute, &components)
+    }
+
+    /// 打开路径指向的文件
+    pub fn open_path(&self, path: &str) -> Result<File, OperationError> {
+        let (parent, name) = self.walk_parent(path)?;
+        let name = name.ok_or_else(|| path_error(path, OperationError::FileNotFound))?;
+        parent
+            .find_file(name)
+            .map(|(_, file)| file)
+            .ok_or_else(|| path_error(name, OperationError::FileNotFound))
+    }
+
+    /// 在路径处创建文件，父目录必须存在
+    pub fn create_file_path(&self, path: &str) -> Result<File, OperationError> {
+        let (parent, name) = self.walk_parent(path)?;
+        let name = name.ok_or_else(|| path_error(path, OperationError::InvalidDirName))?;
+        check_name(name)?;
+        if parent.find_dir(name).is_some() {
+            return Err(path_error(name, OperationError::DirExist));
+        }
+        if parent.find_file(name).is_some() {
+            return Err(path_error(name, OperationError::FileExist));
+        }
+        parent
+            .create_file(name)
+            .map_err(|err| path_error(name, err))?;
+        parent.open_file(name)
+    }
+
+    /// 创建路径中所有不存在的目录
+    pub fn create_dir_all(&self, path: &str) -> Result<Dir, OperationError> {
+        let (absolute, components) = split_path(path);
+        let mut stack = self.start_stack(absolute)?;
+        for component in components {
+            if component == ".." {
+                Self::pop_stack(&mut stack)?;
+                continue;
+            }
+            let dir = stack.last().unwrap();
+            if let Some((_, next)) = dir.find_dir(component) {
+                next.reload()?;
+                stack.push(next);
+                continue;
+            }
+            if dir.find_file(component).is_some() {
+                return Err(path_error(component, OperationError::FileExist));
+            }
+            dir.create_dir(component)
+                .map_err(|err| path_error(component, err))?;
+            let next = dir.open_dir(component)?;
+            stack.push(next);
+        }
+        Ok(stack.pop().unwrap())
+    }
+
+    /// 删除路径指向的文件或目录，目录会被递归删除
+    pub fn remove_path(&self, path: &str) -> Result<(), OperationError> {
+        let (parent, name) = self.walk_parent(path)?;
+        let name = name.ok_or_else(|| path_error(path, OperationError::InvalidDirName))?;
+        if let Some((key, _)) = parent.find_file(name) {
+            return parent
+                .delete_file(&key)
+                .map_err(|err| path_error(name, err));
+        }
+        if let Some((key, _)) = parent.find_dir(name) {
+            return parent.delete_dir(&key).map_err(|err| path_error(name, err));
+        }
+        Err(path_error(name, OperationError::NotFound))
+    }
+
+    /// 路径指向的文件或目录是否存在
+    pub fn exists(&self, path: &str) -> Result<bool, OperationError> {
+        let (parent, name) = match self.walk_parent(path) {
+            Ok(ans) => ans,
+            Err(OperationError::Path { .. }) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        Ok(match name {
+            Some(name) => parent.find_file(name).is_some() || parent.find_dir(name).is_some(),
+            None => true,
+        })
+    }
+}
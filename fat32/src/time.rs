@@ -0,0 +1,201 @@
+//! fat32的时间戳
+//!
+//! 目录项中的时间由挂载时传入的`TimeProvider`提供，
+//! no_std环境下可以实现自己的时钟源，std环境下可以开启`std`特性使用系统时间
+use core::fmt::Debug;
+
+/// 日期与时间
+/// fat32能表示的范围为1980-01-01到2107-12-31，精度为10ms
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    /// 0-23
+    pub hour: u8,
+    /// 0-59
+    pub minute: u8,
+    /// 0-59
+    pub second: u8,
+    /// 0-999
+    pub millisecond: u16,
+}
+
+impl DateTime {
+    /// fat32时间的起点 1980-01-01 00:00:00
+    pub const EPOCH: DateTime = DateTime {
+        year: 1980,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        millisecond: 0,
+    };
+
+    /// 从目录项中的日期、时间以及10ms位解析
+    pub fn from_fat(date: u16, time: u16, tenth: u8) -> Self {
+        Self {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0F) as u8,
+            day: (date & 0x1F) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3F) as u8,
+            second: ((time & 0x1F) * 2) as u8 + tenth / 100,
+            millisecond: (tenth % 100) as u16 * 10,
+        }
+    }
+    /// 超出fat32范围的时间会被截断到边界
+    fn clamp(&self) -> Self {
+        if self.year < 1980 {
+            Self::EPOCH
+        } else if self.year > 2107 {
+            Self {
+                year: 2107,
+                month: 12,
+                day: 31,
+                hour: 23,
+                minute: 59,
+                second: 59,
+                millisecond: 990,
+            }
+        } else {
+            *self
+        }
+    }
+    /// 日期: 年(7位，从1980开始)|月(4位)|日(5位)
+    pub fn fat_date(&self) -> u16 {
+        let t = self.clamp();
+        (t.year - 1980) << 9 | (t.month as u16) << 5 | t.day as u16
+    }
+    /// 时间: 时(5位)|分(6位)|秒/2(5位)
+    pub fn fat_time(&self) -> u16 {
+        let t = self.clamp();
+        (t.hour as u16) << 11 | (t.minute as u16) << 5 | (t.second / 2) as u16
+    }
+    /// 创建时间的10ms位，0-199
+    pub fn fat_tenth(&self) -> u8 {
+        let t = self.clamp();
+        (t.second % 2) * 100 + (t.millisecond / 10) as u8
+    }
+
+    /// 由1970-01-01 00:00:00 UTC以来的毫秒数计算日期
+    pub fn from_unix_millis(millis: u64) -> Self {
+        let secs = millis / 1000;
+        let days = (secs / 86400) as i64;
+        let rem = secs % 86400;
+        // 参考 http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+            millisecond: (millis % 1000) as u16,
+        }
+    }
+}
+
+/// 时钟源
+pub trait TimeProvider: Debug + Send + Sync {
+    /// 当前时间
+    fn now(&self) -> DateTime;
+}
+
+/// 没有时钟时使用，始终返回1980-01-01 00:00:00
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultTimeProvider;
+
+impl TimeProvider for DefaultTimeProvider {
+    fn now(&self) -> DateTime {
+        DateTime::EPOCH
+    }
+}
+
+/// 使用系统时间(UTC)
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdTimeProvider;
+
+#[cfg(feature = "std")]
+impl TimeProvider for StdTimeProvider {
+    fn now(&self) -> DateTime {
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        DateTime::from_unix_millis(millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fat_encode_decode() {
+        let t = DateTime {
+            year: 2023,
+            month: 4,
+            day: 15,
+            hour: 13,
+            minute: 45,
+            second: 31,
+            millisecond: 250,
+        };
+        assert_eq!(t.fat_date(), (43 << 9) | (4 << 5) | 15);
+        assert_eq!(t.fat_time(), (13 << 11) | (45 << 5) | 15);
+        assert_eq!(t.fat_tenth(), 125);
+        assert_eq!(
+            DateTime::from_fat(t.fat_date(), t.fat_time(), t.fat_tenth()),
+            t
+        );
+        // 没有10ms位时只精确到2秒
+        let t = DateTime::from_fat(t.fat_date(), t.fat_time(), 0);
+        assert_eq!((t.second, t.millisecond), (30, 0));
+    }
+
+    #[test]
+    fn test_clamp() {
+        let t = DateTime {
+            year: 1970,
+            ..DateTime::EPOCH
+        };
+        assert_eq!(t.fat_date(), DateTime::EPOCH.fat_date());
+        let t = DateTime {
+            year: 2200,
+            ..DateTime::EPOCH
+        };
+        assert_eq!(DateTime::from_fat(t.fat_date(), 0, 0).year, 2107);
+    }
+
+    #[test]
+    fn test_from_unix_millis() {
+        assert_eq!(DateTime::from_unix_millis(315_532_800_000), DateTime::EPOCH);
+        let t = DateTime::from_unix_millis(1_709_210_096_789);
+        assert_eq!(
+            t,
+            DateTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 12,
+                minute: 34,
+                second: 56,
+                millisecond: 789,
+            }
+        );
+    }
+}
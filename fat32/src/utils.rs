@@ -5,8 +5,22 @@ pub fn u32_from_le_bytes(bytes: &[u8]) -> u32 {
     u32::from_le_bytes(bytes[0..4].try_into().unwrap())
 }
 
+/// fat32的文件名不区分大小写
+pub fn name_eq(a: &str, b: &str) -> bool {
+    if a.is_ascii() && b.is_ascii() {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a.to_uppercase() == b.to_uppercase()
+    }
+}
+
+/// 引导扇区中BPB所占的最小长度
+/// 卷的实际扇区大小由BPB中的`bytes_per_sector`决定
 pub const BLOCK_SIZE: usize = 512;
 
+/// fat32支持的扇区大小
+pub const SECTOR_SIZES: [usize; 4] = [512, 1024, 2048, 4096];
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -35,4 +49,10 @@ mod tests {
         let bytes = [0x01, 0x02, 0x03];
         super::u32_from_le_bytes(&bytes);
     }
+    #[test]
+    fn test_name_eq() {
+        assert!(super::name_eq("readme.txt", "README.TXT"));
+        assert!(super::name_eq("Ärger", "äRGER"));
+        assert!(!super::name_eq("readme.txt", "readme.md"));
+    }
 }